@@ -0,0 +1,313 @@
+//! Lay out a dungeon without opening a window, using the same rules as the
+//! game.
+//!
+//! ```text
+//! cargo run --bin dungeon_layout -- --seed 1234 --radius 5
+//! cargo run --bin dungeon_layout -- --seed 1234 --rooms 40 --format json --output layout.json
+//! ```
+//!
+//! Options:
+//! - `--seed <u64>`: the dungeon seed, as passed to the game. Picked from the
+//!   clock if not given.
+//! - `--generator <name>`: one of the generators the game accepts.
+//! - `--depth <floor>`: which floor to lay out, starting at 0.
+//! - `--themes <distance|noise>`: how cells are sorted into themes, as the
+//!   game does. Only shows up in the JSON layout.
+//! - `--radius <cells>`: place every reachable room this many cells from the
+//!   start hall. Defaults to [DEFAULT_RADIUS].
+//! - `--rooms <count>`: stop after this many rooms instead.
+//! - `--bounds <width>x<height>`: keep the dungeon to a rectangle of cells
+//!   centred on the start hall, as the game does. Every cell in it is filled,
+//!   unless `--radius` or `--rooms` is given as well.
+//! - `--format <ascii|json>`: an ASCII map of the door codes, or the layout as
+//!   JSON, which the `render_layout` tool draws. Defaults to `ascii`. The
+//!   ASCII map marks the start hall with `S`, special rooms with the first
+//!   letter of their role in lower case, and other rooms with stairs down with
+//!   `>`.
+//! - `--output <path>`: write to a file rather than stdout.
+//! - `--project <path>`: the LDtk project the rooms come from. Like the game,
+//!   only the roles and rooms spanning several cells it has rooms for are laid
+//!   out. Defaults to the one the game loads, see
+//!   [project_path](dungeon_of_madness::room_templates::project_path).
+//! - `--layout <name>`: pin rooms to the first floor from
+//!   `assets/layouts/<name>.layout`, as the game does, see
+//!   [pins](dungeon_of_madness::pins). The rooms are looked up in the
+//!   `--project`.
+
+use std::process::ExitCode;
+
+use bevy::prelude::*;
+use serde::Serialize;
+
+use dungeon_of_madness::generator::{self, DungeonGenerator};
+use dungeon_of_madness::launch_option;
+use dungeon_of_madness::layout::{
+    parse_bounds, CellLevel, DungeonGrid, DungeonSeed, ExploreLimit, WALL_DOWN, WALL_LEFT,
+    WALL_RIGHT, WALL_UP,
+};
+use dungeon_of_madness::pins::PinnedLayout;
+use dungeon_of_madness::room_templates::{project_path, RoomTemplates};
+use dungeon_of_madness::themes::ThemeMap;
+
+/// How far from the start hall to lay out the dungeon, if neither `--radius`
+/// nor `--rooms` is given.
+const DEFAULT_RADIUS: i32 = 5;
+
+/// Where the game finds the layout named by `--layout`, relative to the root
+/// of the repository.
+const LAYOUTS_DIR: &str = "assets/layouts";
+
+/// The laid out dungeon, as written by `--format json`.
+#[derive(Serialize)]
+struct LayoutJson {
+    seed: u64,
+    depth: u32,
+    generator: &'static str,
+    rooms: Vec<RoomJson>,
+}
+
+#[derive(Serialize)]
+struct RoomJson {
+    x: i32,
+    y: i32,
+    /// The top left cell of the room, which differs from the cell for rooms
+    /// spanning several cells.
+    anchor: [i32; 2],
+    /// The identifier of the level pinned to the room, if any.
+    template: Option<String>,
+    code: u16,
+    role: Option<&'static str>,
+    theme: &'static str,
+    stairs: bool,
+}
+
+/// Parse a numeric launch option, if given.
+fn numeric_option<T: std::str::FromStr>(name: &str) -> Result<Option<T>, String> {
+    launch_option(name)
+        .map(|value| {
+            value
+                .parse()
+                .map_err(|_| format!("Bad value for --{name}: {value}"))
+        })
+        .transpose()
+}
+
+/// Read the room templates of the `--project`.
+fn room_templates() -> Result<RoomTemplates, String> {
+    let project = launch_option("project").unwrap_or_else(project_path);
+
+    let bytes =
+        std::fs::read(&project).map_err(|error| format!("Could not read {project}: {error}"))?;
+    let (room_templates, _) = RoomTemplates::from_project_json(&bytes)
+        .map_err(|error| format!("Could not parse {project}: {error}"))?;
+
+    Ok(room_templates)
+}
+
+/// Pin the rooms of the layout with the given name, looking them up in the
+/// room templates.
+fn pin_rooms(
+    grid: &mut DungeonGrid,
+    name: &str,
+    room_templates: &RoomTemplates,
+) -> Result<(), String> {
+    let path = format!("{LAYOUTS_DIR}/{name}.layout");
+
+    let text = std::fs::read_to_string(&path)
+        .map_err(|error| format!("Could not read {path}: {error}"))?;
+    let pinned_layout =
+        PinnedLayout::parse(&text).map_err(|error| format!("Could not read {path}: {error}"))?;
+
+    grid.place_start_hall(None, CellLevel::Unloaded);
+
+    match &pinned_layout.apply(grid, room_templates)[..] {
+        [] => Ok(()),
+        errors => Err(errors
+            .iter()
+            .map(|error| format!("Could not pin a room from {path}: {error}"))
+            .collect::<Vec<_>>()
+            .join("\n")),
+    }
+}
+
+/// Draw each room as a 3x3 block of characters, with `#` for walls and the door
+/// code in hex in the middle. The start hall is marked with `S`, special rooms
+/// with the first letter of their role, and other rooms with stairs down with
+/// `>`. North is up.
+fn ascii_map(seed: &DungeonSeed, grid: &DungeonGrid) -> String {
+    let Some((min, max)) = grid
+        .keys()
+        .fold(None, |bounds: Option<(IVec2, IVec2)>, cell| {
+            Some(bounds.map_or((*cell, *cell), |(min, max)| {
+                (min.min(*cell), max.max(*cell))
+            }))
+        })
+    else {
+        return String::new();
+    };
+
+    let mut map = String::new();
+
+    for y in (min.y..=max.y).rev() {
+        let mut rows = [String::new(), String::new(), String::new()];
+
+        for x in min.x..=max.x {
+            let cell = IVec2::new(x, y);
+
+            let Some(grid_cell) = grid.get(&cell) else {
+                for row in &mut rows {
+                    row.push_str("   ");
+                }
+                continue;
+            };
+
+            let code = grid_cell.code;
+            let wall = |wall: u16| if code & wall != 0 { '#' } else { ' ' };
+            let centre = if cell == IVec2::ZERO {
+                'S'
+            } else if let Some(role) = grid_cell.role {
+                role.name()
+                    .chars()
+                    .next()
+                    .map_or('?', |initial| initial.to_ascii_lowercase())
+            } else if grid.has_stairs(seed, cell) {
+                '>'
+            } else {
+                char::from_digit(code as u32, 16).unwrap_or('?')
+            };
+
+            rows[0].extend(['#', wall(WALL_UP), '#']);
+            rows[1].extend([wall(WALL_LEFT), centre, wall(WALL_RIGHT)]);
+            rows[2].extend(['#', wall(WALL_DOWN), '#']);
+        }
+
+        for row in rows {
+            map.push_str(row.trim_end());
+            map.push('\n');
+        }
+    }
+
+    map
+}
+
+fn layout_json(
+    seed: &DungeonSeed,
+    depth: u32,
+    generator: &dyn DungeonGenerator,
+    theme_map: ThemeMap,
+    grid: &DungeonGrid,
+    room_templates: &RoomTemplates,
+) -> String {
+    let mut rooms: Vec<RoomJson> = grid
+        .iter()
+        .map(|(cell, grid_cell)| RoomJson {
+            x: cell.x,
+            y: cell.y,
+            anchor: grid_cell.anchor.to_array(),
+            template: grid_cell
+                .template
+                .and_then(|iid| room_templates.by_iid(iid))
+                .map(|room| room.identifier.clone()),
+            code: grid_cell.code,
+            role: grid_cell.role.map(|role| role.name()),
+            theme: theme_map.theme(&seed.floor(depth), *cell).name,
+            stairs: grid.has_stairs(&seed.floor(depth), *cell),
+        })
+        .collect();
+
+    rooms.sort_by_key(|room| (-room.y, room.x));
+
+    let layout = LayoutJson {
+        seed: **seed,
+        depth,
+        generator: generator.name(),
+        rooms,
+    };
+
+    // Serializing plain structs into a string can't fail.
+    serde_json::to_string_pretty(&layout).expect("layout should serialize") + "\n"
+}
+
+fn run() -> Result<(), String> {
+    // Unlike the game, bad values are errors, rather than quietly laying out
+    // some other dungeon.
+    let seed = match numeric_option("seed")? {
+        Some(seed) => DungeonSeed(seed),
+        None => DungeonSeed::from_env(),
+    };
+    let theme_map = match launch_option("themes") {
+        Some(name) => ThemeMap::by_name(&name)
+            .ok_or_else(|| format!("Unknown themes: {name}. Expected distance or noise"))?,
+        None => ThemeMap::default(),
+    };
+    let depth = numeric_option("depth")?.unwrap_or(0);
+    let floor_seed = seed.floor(depth);
+
+    let generator = match launch_option("generator") {
+        Some(name) => generator::by_name(&name).ok_or_else(|| {
+            format!(
+                "Unknown generator: {name}. Expected one of: {}",
+                generator::GENERATOR_NAMES.join(", ")
+            )
+        })?,
+        None => Box::new(generator::RandomGenerator),
+    };
+
+    let mut grid = match launch_option("bounds") {
+        Some(value) => DungeonGrid::bounded(parse_bounds(&value).ok_or_else(|| {
+            format!("Bad value for --bounds: {value}. Expected <width>x<height>, at least 3x3")
+        })?),
+        None => DungeonGrid::default(),
+    };
+
+    // Like the game, special rooms can only be given the roles, and doors,
+    // there are rooms for. Likewise for rooms spanning several cells.
+    let room_templates = room_templates()?;
+    grid.role_codes = room_templates.role_codes();
+    grid.footprints = room_templates.footprints();
+
+    let limit = match (numeric_option("radius")?, numeric_option("rooms")?) {
+        (Some(_), Some(_)) => return Err("Pass either --radius or --rooms, not both".into()),
+        (_, Some(rooms)) => ExploreLimit::Rooms(rooms),
+        (Some(radius), None) => ExploreLimit::Radius(radius),
+        (None, None) if grid.bounds.is_some() => ExploreLimit::Rooms(usize::MAX),
+        (None, None) => ExploreLimit::Radius(DEFAULT_RADIUS),
+    };
+
+    if let Some(name) = launch_option("layout").filter(|_| depth == 0) {
+        pin_rooms(&mut grid, &name, &room_templates)?;
+    }
+
+    grid.explore(&floor_seed, &*generator, limit);
+
+    let output = match launch_option("format").as_deref() {
+        None | Some("ascii") => ascii_map(&floor_seed, &grid),
+        Some("json") => layout_json(&seed, depth, &*generator, theme_map, &grid, &room_templates),
+        Some(format) => return Err(format!("Unknown format: {format}. Expected ascii or json")),
+    };
+
+    match launch_option("output") {
+        Some(path) => std::fs::write(&path, output)
+            .map_err(|error| format!("Could not write {path}: {error}"))?,
+        None => print!("{output}"),
+    }
+
+    eprintln!(
+        "Dungeon seed: {}, depth: {depth}, generator: {}, rooms: {}",
+        *seed,
+        generator.name(),
+        grid.len()
+    );
+
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("{error}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,47 @@
+//! Check the LDtk project for authoring mistakes, see
+//! [lint](dungeon_of_madness::lint).
+//!
+//! ```text
+//! cargo run --bin lint_project
+//! ```
+//!
+//! Prints every problem found, and fails if there are any.
+//!
+//! Options:
+//! - `--project <path>`: the LDtk project to check. Defaults to the one the
+//!   game loads, see [project_path].
+
+use std::process::ExitCode;
+
+use dungeon_of_madness::launch_option;
+use dungeon_of_madness::lint::lint_project;
+use dungeon_of_madness::room_templates::project_path;
+
+fn run() -> Result<(), String> {
+    let path = launch_option("project").unwrap_or_else(project_path);
+
+    let bytes = std::fs::read(&path).map_err(|error| format!("Could not read {path}: {error}"))?;
+    let problems =
+        lint_project(&bytes).map_err(|error| format!("Could not parse {path}: {error}"))?;
+
+    for problem in &problems {
+        eprintln!("{problem}");
+    }
+
+    if problems.is_empty() {
+        eprintln!("No problems found in {path}");
+        Ok(())
+    } else {
+        Err(format!("Found {} problems in {path}", problems.len()))
+    }
+}
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("{error}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,116 @@
+//! Write the mirrored copies of the room templates into the LDtk project, see
+//! [mirror](dungeon_of_madness::mirror).
+//!
+//! ```text
+//! cargo run --bin mirror_rooms
+//! cargo run --bin mirror_rooms -- --check
+//! ```
+//!
+//! Every copy is made again from its original, and copies whose original is
+//! gone, or no longer asks to be mirrored, are removed. A copy keeps its uid
+//! from one run to the next, so the project only changes where the rooms did.
+//!
+//! Options:
+//! - `--project <path>`: the LDtk project to update. Defaults to the one the
+//!   game loads, see [project_path].
+//! - `--check`: fail if the copies are out of date, rather than writing them.
+
+use std::collections::HashMap;
+use std::process::ExitCode;
+
+use serde_json::Value;
+
+use dungeon_of_madness::launch_option;
+use dungeon_of_madness::ldtk_json::{replace_next_uid, replace_world_levels};
+use dungeon_of_madness::mirror::{level_field, Mirror, MIRROR_OF_FIELD};
+use dungeon_of_madness::room_templates::{project_path, DUNGEON_WORLD};
+
+fn run() -> Result<(), String> {
+    let path = launch_option("project").unwrap_or_else(project_path);
+    let check = std::env::args().any(|arg| arg == "--check");
+
+    let text = std::fs::read_to_string(&path)
+        .map_err(|error| format!("Could not read {path}: {error}"))?;
+    let project: Value =
+        serde_json::from_str(&text).map_err(|error| format!("Could not parse {path}: {error}"))?;
+
+    let mut next_uid = project["nextUid"]
+        .as_i64()
+        .ok_or_else(|| format!("{path} has no nextUid"))?;
+
+    let levels = project["worlds"]
+        .as_array()
+        .and_then(|worlds| {
+            worlds
+                .iter()
+                .find(|world| world["identifier"] == DUNGEON_WORLD)
+        })
+        .and_then(|world| world["levels"].as_array())
+        .ok_or_else(|| format!("{path} has no {DUNGEON_WORLD} world"))?;
+
+    let (copies, originals): (Vec<_>, Vec<_>) = levels
+        .iter()
+        .partition(|level| !level_field(level, MIRROR_OF_FIELD).is_null());
+
+    let copy_uids: HashMap<_, _> = copies
+        .iter()
+        .filter_map(|level| Some((level["identifier"].as_str()?, level["uid"].as_i64()?)))
+        .collect();
+
+    let mut mirrored_levels = Vec::new();
+    let mut copy_count = 0;
+
+    for level in originals {
+        mirrored_levels.push(level.clone());
+
+        for mirror in Mirror::ALL {
+            if level_field(level, mirror.field()) != &Value::Bool(true) {
+                continue;
+            }
+
+            let identifier = mirror.identifier(level["identifier"].as_str().unwrap_or_default());
+            let uid = copy_uids
+                .get(identifier.as_str())
+                .copied()
+                .unwrap_or_else(|| {
+                    next_uid += 1;
+                    next_uid - 1
+                });
+
+            mirrored_levels.push(
+                mirror
+                    .level(level, uid)
+                    .map_err(|error| error.to_string())?,
+            );
+            copy_count += 1;
+        }
+    }
+
+    let mirrored_text = replace_world_levels(&text, DUNGEON_WORLD, &mirrored_levels)
+        .and_then(|text| replace_next_uid(&text, next_uid))
+        .ok_or_else(|| format!("Could not find the {DUNGEON_WORLD} levels in {path}"))?;
+
+    if mirrored_text == text {
+        eprintln!("{copy_count} mirrored rooms in {path} are up to date");
+    } else if check {
+        return Err(format!(
+            "The mirrored rooms in {path} are out of date. Run: cargo run --bin mirror_rooms"
+        ));
+    } else {
+        std::fs::write(&path, mirrored_text)
+            .map_err(|error| format!("Could not write {path}: {error}"))?;
+        eprintln!("Wrote {copy_count} mirrored rooms to {path}");
+    }
+
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("{error}");
+            ExitCode::FAILURE
+        }
+    }
+}
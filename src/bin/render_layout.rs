@@ -0,0 +1,199 @@
+//! Draw a laid out dungeon into a PNG image, without opening a window, see
+//! [render](dungeon_of_madness::render).
+//!
+//! ```text
+//! cargo run --bin dungeon_layout -- --seed 1234 --format json --output layout.json
+//! cargo run --bin render_layout -- --input layout.json --output dungeon.png
+//! ```
+//!
+//! Rooms are picked from the project the way the game picks them, from the
+//! seed, depth, and cells of the layout, so the image shows the dungeon as it
+//! would be played. Rooms pinned by the layout are drawn as pinned. The
+//! layout must be made from the same project, or some of its rooms may have
+//! no level to draw.
+//!
+//! Options:
+//! - `--input <path>`: the layout, as written by `dungeon_layout --format
+//!   json`.
+//! - `--output <path>`: where to write the image. Defaults to
+//!   [DEFAULT_OUTPUT].
+//! - `--project <path>`: the LDtk project to draw the rooms from. Defaults to
+//!   the one the game loads, see [project_path].
+//! - `--tileset <path>`: the tileset the rooms are drawn with. Defaults to
+//!   [DEFAULT_TILESET].
+
+use std::process::ExitCode;
+
+use bevy::prelude::*;
+use serde::Deserialize;
+
+use dungeon_of_madness::launch_option;
+use dungeon_of_madness::layout::{CellLevel, DungeonGrid, DungeonSeed, GridCell};
+use dungeon_of_madness::render::{render, ProjectTiles, RenderedRoom, RgbaImage};
+use dungeon_of_madness::roles::RoomRole;
+use dungeon_of_madness::room_templates::{project_path, RoomTemplates, START_HALL};
+
+/// The tileset the game draws the rooms with.
+const DEFAULT_TILESET: &str = "assets/tilesets/Dungeon_Tileset_v2.png";
+
+const DEFAULT_OUTPUT: &str = "dungeon.png";
+
+/// The laid out dungeon, as written by `dungeon_layout --format json`.
+#[derive(Deserialize)]
+struct LayoutJson {
+    seed: u64,
+    depth: u32,
+    rooms: Vec<RoomJson>,
+}
+
+#[derive(Deserialize)]
+struct RoomJson {
+    x: i32,
+    y: i32,
+    anchor: [i32; 2],
+    template: Option<String>,
+    code: u16,
+    role: Option<String>,
+    theme: String,
+    stairs: bool,
+}
+
+/// Read a file, with the path in the error.
+fn read(path: &str) -> Result<Vec<u8>, String> {
+    std::fs::read(path).map_err(|error| format!("Could not read {path}: {error}"))
+}
+
+fn run() -> Result<(), String> {
+    let input = launch_option("input").ok_or("Pass the layout to draw with --input")?;
+    let output = launch_option("output").unwrap_or_else(|| DEFAULT_OUTPUT.to_string());
+    let project = launch_option("project").unwrap_or_else(project_path);
+    let tileset = launch_option("tileset").unwrap_or_else(|| DEFAULT_TILESET.to_string());
+
+    let layout: LayoutJson = serde_json::from_slice(&read(&input)?)
+        .map_err(|error| format!("Could not parse {input}: {error}"))?;
+
+    let bytes = read(&project)?;
+    let parse_error = |error: serde_json::Error| format!("Could not parse {project}: {error}");
+    let (room_templates, _) = RoomTemplates::from_project_json(&bytes).map_err(parse_error)?;
+    let project_tiles = ProjectTiles::from_project_json(&bytes).map_err(parse_error)?;
+
+    let tileset_image = image::open(&tileset)
+        .map_err(|error| format!("Could not read {tileset}: {error}"))?
+        .to_rgba8();
+    let tileset_image = RgbaImage {
+        size: UVec2::new(tileset_image.width(), tileset_image.height()),
+        pixels: tileset_image.into_raw(),
+    };
+
+    let seed = DungeonSeed(layout.seed).floor(layout.depth);
+
+    // The cells of the layout, to read the footprints of rooms spanning
+    // several cells back from.
+    let mut grid = DungeonGrid::default();
+    for room in &layout.rooms {
+        let role = match room.role.as_deref() {
+            Some(name) => Some(RoomRole::from_name(name).ok_or_else(|| {
+                format!(
+                    "Unknown role {name} for the room at {}, {} in {input}. Expected one of: {}",
+                    room.x,
+                    room.y,
+                    RoomRole::ALL.map(RoomRole::name).join(", ")
+                )
+            })?),
+            None => None,
+        };
+
+        grid.place(
+            IVec2::new(room.x, room.y),
+            GridCell {
+                code: room.code,
+                anchor: IVec2::from(room.anchor),
+                template: None,
+                role,
+                level: CellLevel::Unloaded,
+            },
+        );
+    }
+
+    let mut rooms = Vec::new();
+
+    // Rooms spanning several cells are drawn once, from their top left cell.
+    for room in layout
+        .rooms
+        .iter()
+        .filter(|room| IVec2::new(room.x, room.y) == IVec2::from(room.anchor))
+    {
+        let anchor = IVec2::from(room.anchor);
+
+        let identifier = match &room.template {
+            Some(identifier) => identifier.clone(),
+            None if anchor == IVec2::ZERO => START_HALL.to_string(),
+            None => {
+                let role = grid[&anchor].role;
+
+                room_templates
+                    .pick(
+                        &seed,
+                        anchor,
+                        room.code,
+                        role,
+                        grid.room_footprint(anchor).as_ref(),
+                        &room.theme,
+                    )
+                    .ok_or_else(|| {
+                        format!(
+                            "There is no room template in {project} with door code {} and role \
+                             {:?}, for the room at {anchor}",
+                            room.code, room.role
+                        )
+                    })?
+                    .identifier
+                    .clone()
+            }
+        };
+
+        let tiles = project_tiles
+            .level(&identifier)
+            .ok_or_else(|| format!("There is no level {identifier} in {project}"))?;
+
+        rooms.push(RenderedRoom { anchor, tiles });
+    }
+
+    let stairs: Vec<IVec2> = layout
+        .rooms
+        .iter()
+        .filter(|room| room.stairs)
+        .map(|room| IVec2::new(room.x, room.y))
+        .collect();
+
+    let image = render(&rooms, &stairs, &tileset_image)
+        .ok_or_else(|| format!("There are no rooms in {input}"))?;
+
+    image::save_buffer(
+        &output,
+        &image.pixels,
+        image.size.x,
+        image.size.y,
+        image::ExtendedColorType::Rgba8,
+    )
+    .map_err(|error| format!("Could not write {output}: {error}"))?;
+
+    eprintln!(
+        "Drew {} rooms of seed {}, depth {}, to {output}",
+        rooms.len(),
+        layout.seed,
+        layout.depth
+    );
+
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("{error}");
+            ExitCode::FAILURE
+        }
+    }
+}
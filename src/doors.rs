@@ -0,0 +1,173 @@
+//! The door code rules, on their own: which walls a room may have, given the
+//! rooms around it.
+//!
+//! A door code has a bit set for each closed wall: [WALL_UP], [WALL_RIGHT],
+//! [WALL_DOWN], and [WALL_LEFT]. Two neighbouring rooms must agree on the wall
+//! between them, so a room's code is constrained by the codes of its
+//! [Neighbours]. Nothing in here knows about grids, levels, or randomness.
+
+pub const WALL_UP: u16 = 0x1;
+pub const WALL_RIGHT: u16 = 0x2;
+pub const WALL_DOWN: u16 = 0x4;
+pub const WALL_LEFT: u16 = 0x8;
+
+/// Every wall closed.
+pub const ALL_WALLS: u16 = WALL_UP | WALL_RIGHT | WALL_DOWN | WALL_LEFT;
+
+/// The wall on the other side of the given wall, as seen from the neighbour.
+pub fn opposite_wall(wall: u16) -> u16 {
+    match wall {
+        WALL_UP => WALL_DOWN,
+        WALL_RIGHT => WALL_LEFT,
+        WALL_DOWN => WALL_UP,
+        WALL_LEFT => WALL_RIGHT,
+        _ => panic!("not a single wall: {wall:#x}"),
+    }
+}
+
+/// The door codes of the rooms around a cell, `None` where no room has been
+/// placed (yet).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Neighbours {
+    pub up: Option<u16>,
+    pub right: Option<u16>,
+    pub down: Option<u16>,
+    pub left: Option<u16>,
+}
+
+impl Neighbours {
+    /// Each side of the room, as the wall on this side along with the code of
+    /// the neighbour on that side.
+    pub fn sides(&self) -> [(u16, Option<u16>); 4] {
+        [
+            (WALL_UP, self.up),
+            (WALL_RIGHT, self.right),
+            (WALL_DOWN, self.down),
+            (WALL_LEFT, self.left),
+        ]
+    }
+
+    /// The walls which are decided by the neighbours, as a `(mask, walls)`
+    /// pair. Each bit set in `mask` must have the same value in the room's
+    /// code as it has in `walls`.
+    pub fn constraints(&self) -> (u16, u16) {
+        let mut mask = 0;
+        let mut walls = 0;
+
+        for (wall, code) in self.sides() {
+            if let Some(code) = code {
+                mask |= wall;
+
+                if code & opposite_wall(wall) != 0 {
+                    walls |= wall;
+                }
+            }
+        }
+
+        (mask, walls)
+    }
+
+    /// The walls with no neighbour behind them, which are free to be picked.
+    pub fn free_walls(&self) -> u16 {
+        let (mask, _) = self.constraints();
+        ALL_WALLS & !mask
+    }
+
+    /// Whether a room with the given code agrees with every neighbour on the
+    /// walls they share.
+    pub fn agrees(&self, code: u16) -> bool {
+        let (mask, walls) = self.constraints();
+        code & mask == walls
+    }
+}
+
+/// Force the walls of `code` shared with its neighbours to match them. Walls
+/// without a neighbour are left as they are.
+///
+/// The result always [agrees](Neighbours::agrees) with `neighbours`.
+pub fn constrain(code: u16, neighbours: &Neighbours) -> u16 {
+    let (mask, walls) = neighbours.constraints();
+    (code & ALL_WALLS & !mask) | walls
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every possible neighbour code, including no neighbour at all.
+    fn neighbour_codes() -> impl Iterator<Item = Option<u16>> + Clone {
+        std::iter::once(None).chain((0..=ALL_WALLS).map(Some))
+    }
+
+    /// Every possible combination of neighbours around a cell.
+    fn all_neighbours() -> impl Iterator<Item = Neighbours> {
+        neighbour_codes().flat_map(|up| {
+            neighbour_codes().flat_map(move |right| {
+                neighbour_codes().flat_map(move |down| {
+                    neighbour_codes().map(move |left| Neighbours {
+                        up,
+                        right,
+                        down,
+                        left,
+                    })
+                })
+            })
+        })
+    }
+
+    #[test]
+    fn constrained_code_agrees_with_every_neighbour() {
+        for neighbours in all_neighbours() {
+            for code in 0..=ALL_WALLS {
+                let constrained = constrain(code, &neighbours);
+
+                for (wall, neighbour) in neighbours.sides() {
+                    if let Some(neighbour) = neighbour {
+                        assert_eq!(
+                            constrained & wall != 0,
+                            neighbour & opposite_wall(wall) != 0,
+                            "code {code:#x} constrained to {constrained:#x} disagrees with \
+                             {neighbours:?} on wall {wall:#x}",
+                        );
+                    }
+                }
+
+                assert!(neighbours.agrees(constrained));
+            }
+        }
+    }
+
+    #[test]
+    fn constrain_keeps_free_walls() {
+        for neighbours in all_neighbours() {
+            let free_walls = neighbours.free_walls();
+
+            for code in 0..=ALL_WALLS {
+                assert_eq!(constrain(code, &neighbours) & free_walls, code & free_walls);
+            }
+        }
+    }
+
+    #[test]
+    fn constrain_leaves_agreeing_codes_alone() {
+        for neighbours in all_neighbours() {
+            for code in (0..=ALL_WALLS).filter(|code| neighbours.agrees(*code)) {
+                assert_eq!(constrain(code, &neighbours), code);
+            }
+        }
+    }
+
+    #[test]
+    fn constrain_ignores_bits_above_the_walls() {
+        assert_eq!(constrain(0xFFF0, &Neighbours::default()), 0);
+        assert_eq!(constrain(0xFFFF, &Neighbours::default()), ALL_WALLS);
+    }
+
+    #[test]
+    fn opposite_wall_is_symmetric() {
+        for wall in [WALL_UP, WALL_RIGHT, WALL_DOWN, WALL_LEFT] {
+            assert_ne!(opposite_wall(wall), wall);
+            assert_eq!(opposite_wall(opposite_wall(wall)), wall);
+        }
+    }
+}
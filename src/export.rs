@@ -0,0 +1,261 @@
+//! Exporting a floor of the dungeon, as it was laid out in a run, into a new
+//! world of the LDtk project, so designers can keep a layout they like and
+//! refine it by hand in the editor.
+//!
+//! Each room becomes a copy of its template's level, placed where the room
+//! was in the dungeon. The world is laid out freely, so the copies can be
+//! moved around in the editor. Exporting to a world which already exists
+//! replaces its levels, but the `Dungeon` world itself is never touched.
+
+use bevy::prelude::*;
+use serde_json::{json, Value};
+
+use crate::layout::LEVEL_SIZE;
+use crate::ldtk_json::{fnv1a, insert_world, masked_iid, replace_next_uid, replace_world_levels};
+use crate::room_templates::DUNGEON_WORLD;
+
+/// A room of the dungeon to export.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ExportedRoom {
+    /// The iid of the room's template.
+    pub template: u128,
+    /// The top left cell of the room.
+    pub anchor: IVec2,
+}
+
+/// The identifier of the world a floor of the dungeon is exported to.
+pub fn world_identifier(seed: u64, depth: u32) -> String {
+    format!("Seed_{seed}_Floor_{depth}")
+}
+
+/// Write the given rooms into the world with the given identifier, in the
+/// text of an LDtk project. The world is added if the project doesn't have it
+/// yet.
+///
+/// Rooms are written top row first, so exporting the same rooms again gives
+/// the same levels.
+pub fn export_world(
+    text: &str,
+    world: &str,
+    rooms: &[ExportedRoom],
+) -> Result<String, ExportError> {
+    if world == DUNGEON_WORLD {
+        return Err(ExportError::DungeonWorld);
+    }
+
+    let project: Value = serde_json::from_str(text)?;
+    let worlds = project["worlds"]
+        .as_array()
+        .ok_or(ExportError::Malformed("worlds"))?;
+
+    let templates = worlds
+        .iter()
+        .find(|candidate| candidate["identifier"] == DUNGEON_WORLD)
+        .and_then(|dungeon| dungeon["levels"].as_array())
+        .ok_or(ExportError::Malformed(DUNGEON_WORLD))?;
+    let existing = worlds
+        .iter()
+        .find(|candidate| candidate["identifier"] == world);
+
+    // Every iid in the world is made from the iid it was copied from, masked
+    // with a hash of the world's identifier and the index of the room.
+    let world_mask = (fnv1a(world) as u128) << 64;
+
+    let mut next_uid = project["nextUid"]
+        .as_i64()
+        .ok_or(ExportError::Malformed("nextUid"))?;
+
+    // Levels being replaced give up their uids to the new ones.
+    let mut free_uids: Vec<i64> = existing
+        .and_then(|existing| existing["levels"].as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(|level| level["uid"].as_i64())
+        .collect();
+    free_uids.sort_unstable_by(|a, b| b.cmp(a));
+
+    let mut rooms = rooms.to_vec();
+    rooms.sort_by_key(|room| (-room.anchor.y, room.anchor.x));
+
+    let mut levels = Vec::new();
+
+    for (index, room) in rooms.iter().enumerate() {
+        let template = templates
+            .iter()
+            .find(|level| {
+                level["iid"]
+                    .as_str()
+                    .and_then(|iid| u128::from_str_radix(&iid.replace('-', ""), 16).ok())
+                    == Some(room.template)
+            })
+            .ok_or(ExportError::UnknownTemplate(room.template))?;
+
+        let uid = free_uids.pop().unwrap_or_else(|| {
+            next_uid += 1;
+            next_uid - 1
+        });
+        let mask = world_mask | (index as u128 + 1);
+
+        levels.push(
+            exported_level(template, world, index + 1, uid, mask, room.anchor)
+                .ok_or(ExportError::Malformed("room template"))?,
+        );
+    }
+
+    let text = match existing {
+        Some(_) => replace_world_levels(text, world, &levels),
+        None => {
+            let iid = project["iid"]
+                .as_str()
+                .and_then(|iid| masked_iid(iid, world_mask))
+                .ok_or(ExportError::Malformed("iid"))?;
+
+            insert_world(
+                text,
+                &json!({
+                    "iid": iid,
+                    "identifier": world,
+                    "defaultLevelWidth": LEVEL_SIZE as i32,
+                    "defaultLevelHeight": LEVEL_SIZE as i32,
+                    "worldGridWidth": LEVEL_SIZE as i32,
+                    "worldGridHeight": LEVEL_SIZE as i32,
+                    "worldLayout": "Free",
+                    "levels": levels,
+                }),
+            )
+        }
+    };
+
+    text.and_then(|text| replace_next_uid(&text, next_uid))
+        .ok_or(ExportError::Malformed("worlds"))
+}
+
+/// A copy of the given template level, as the given room of a world, placed at
+/// the given cell.
+fn exported_level(
+    template: &Value,
+    world: &str,
+    number: usize,
+    uid: i64,
+    mask: u128,
+    anchor: IVec2,
+) -> Option<Value> {
+    let identifier = template["identifier"].as_str()?;
+    // LDtk worlds have y going down.
+    let location = anchor * IVec2::new(1, -1) * LEVEL_SIZE as i32;
+
+    let mut level = template.clone();
+    level["identifier"] = format!("{world}_{number}_{identifier}").into();
+    level["iid"] = masked_iid(template["iid"].as_str()?, mask)?.into();
+    level["uid"] = uid.into();
+    level["worldX"] = location.x.into();
+    level["worldY"] = location.y.into();
+    level["useAutoIdentifier"] = false.into();
+    level["__neighbours"] = json!([]);
+
+    for layer in level["layerInstances"].as_array_mut()? {
+        layer["iid"] = masked_iid(layer["iid"].as_str()?, mask)?.into();
+        layer["levelId"] = uid.into();
+
+        for entity in layer["entityInstances"].as_array_mut()? {
+            entity["iid"] = masked_iid(entity["iid"].as_str()?, mask)?.into();
+
+            if let (Some(x), Some(y)) = (entity["px"][0].as_i64(), entity["px"][1].as_i64()) {
+                if entity.get("__worldX").is_some() {
+                    entity["__worldX"] = (location.x as i64 + x).into();
+                    entity["__worldY"] = (location.y as i64 + y).into();
+                }
+            }
+        }
+    }
+
+    Some(level)
+}
+
+/// Reasons a floor can't be exported.
+#[derive(Debug, thiserror::Error)]
+pub enum ExportError {
+    #[error("could not parse LDtk project: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("the LDtk project has a malformed {0}")]
+    Malformed(&'static str),
+    #[error("the {DUNGEON_WORLD} world holds the room templates, and can't be exported to")]
+    DungeonWorld,
+    #[error("there is no room template with iid {0:032x}")]
+    UnknownTemplate(u128),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::room_templates::RoomTemplates;
+
+    const PROJECT: &str = include_str!("../assets/ldtk/dungeon_of_madness.ldtk");
+
+    #[test]
+    fn rooms_are_placed_at_their_cells() {
+        let (room_templates, _) = RoomTemplates::from_project_json(PROJECT.as_bytes()).unwrap();
+        let start_hall = room_templates.by_identifier("Start_Hall").unwrap().iid;
+        let long_hall = room_templates.by_identifier("Long_Hall").unwrap().iid;
+
+        let rooms = [
+            ExportedRoom {
+                template: long_hall,
+                anchor: IVec2::new(1, 0),
+            },
+            ExportedRoom {
+                template: start_hall,
+                anchor: IVec2::ZERO,
+            },
+        ];
+        let world = world_identifier(1234, 0);
+        let text = export_world(PROJECT, &world, &rooms).unwrap();
+
+        let project: Value = serde_json::from_str(&text).unwrap();
+        let original: Value = serde_json::from_str(PROJECT).unwrap();
+        let next_uid = original["nextUid"].as_i64().unwrap();
+
+        assert_eq!(project["worlds"][0], original["worlds"][0]);
+        assert_eq!(project["nextUid"], next_uid + 2);
+
+        let levels = project["worlds"][1]["levels"].as_array().unwrap();
+        assert_eq!(project["worlds"][1]["identifier"], "Seed_1234_Floor_0");
+        assert_eq!(levels.len(), 2);
+        assert_eq!(levels[0]["identifier"], "Seed_1234_Floor_0_1_Start_Hall");
+        assert_eq!(levels[1]["identifier"], "Seed_1234_Floor_0_2_Long_Hall");
+        assert_eq!(levels[1]["worldX"], LEVEL_SIZE as i32);
+        assert_eq!(levels[1]["worldY"], 0);
+        assert_eq!(levels[1]["uid"], next_uid + 1);
+
+        // The copy of the start hall has its own skeleton.
+        let skeleton_iid = |level: &Value| {
+            level["layerInstances"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .flat_map(|layer| layer["entityInstances"].as_array().unwrap())
+                .find(|entity| entity["__identifier"] == "Skeleton")
+                .unwrap()["iid"]
+                .clone()
+        };
+        let original_start_hall = original["worlds"][0]["levels"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|level| level["identifier"] == "Start_Hall")
+            .unwrap();
+        assert_ne!(skeleton_iid(&levels[0]), skeleton_iid(original_start_hall));
+
+        // Exporting again replaces the levels, and reuses their uids.
+        let again = export_world(&text, &world, &rooms[..1]).unwrap();
+        let project: Value = serde_json::from_str(&again).unwrap();
+        assert_eq!(project["worlds"].as_array().unwrap().len(), 2);
+        assert_eq!(project["worlds"][1]["levels"].as_array().unwrap().len(), 1);
+        assert_eq!(project["nextUid"], next_uid + 2);
+
+        assert!(matches!(
+            export_world(PROJECT, DUNGEON_WORLD, &rooms),
+            Err(ExportError::DungeonWorld)
+        ));
+    }
+}
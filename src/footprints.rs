@@ -0,0 +1,151 @@
+//! Rooms which span more than one grid cell, like long halls and arenas.
+//!
+//! A room's [Footprint] is the block of cells it covers, at most
+//! [MAX_ROOM_SIZE]. Each covered cell gets its own door code in the
+//! [DungeonGrid](crate::layout::DungeonGrid), with the walls between cells of
+//! the same room left open, so the rooms around it still only ever share a
+//! wall with a single cell.
+//!
+//! Doors are declared per edge segment, one segment for each cell along an
+//! edge. Segments are counted left to right along the top and bottom edges,
+//! and top to bottom along the left and right edges.
+
+use bevy::prelude::*;
+
+use crate::doors::{WALL_DOWN, WALL_LEFT, WALL_RIGHT, WALL_UP};
+
+/// The most cells a room may span, across and down.
+pub const MAX_ROOM_SIZE: IVec2 = IVec2::new(2, 2);
+
+/// The cells covered by a room, along with the door code of each.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Footprint {
+    /// How many cells across and down the room is.
+    size: IVec2,
+    /// The door code of each covered cell, row by row from the top left.
+    codes: Vec<u16>,
+}
+
+impl Footprint {
+    /// A room of the given size, with `closed(wall, segment)` saying whether
+    /// each segment of each edge is walled off.
+    ///
+    /// Returns `None` if the size is empty or larger than [MAX_ROOM_SIZE].
+    pub fn from_segments(size: IVec2, closed: impl Fn(u16, i32) -> bool) -> Option<Self> {
+        if size.min_element() < 1 || size.cmpgt(MAX_ROOM_SIZE).any() {
+            return None;
+        }
+
+        let mut codes = Vec::new();
+
+        for y in 0..size.y {
+            for x in 0..size.x {
+                let mut code = 0;
+
+                if y == 0 && closed(WALL_UP, x) {
+                    code |= WALL_UP;
+                }
+                if x == size.x - 1 && closed(WALL_RIGHT, y) {
+                    code |= WALL_RIGHT;
+                }
+                if y == size.y - 1 && closed(WALL_DOWN, x) {
+                    code |= WALL_DOWN;
+                }
+                if x == 0 && closed(WALL_LEFT, y) {
+                    code |= WALL_LEFT;
+                }
+
+                codes.push(code);
+            }
+        }
+
+        Some(Self { size, codes })
+    }
+
+    /// How many segments the given edge of a room of the given size has.
+    pub fn segments(size: IVec2, wall: u16) -> i32 {
+        match wall {
+            WALL_UP | WALL_DOWN => size.x,
+            _ => size.y,
+        }
+    }
+
+    /// How many cells across and down the room is.
+    pub fn size(&self) -> IVec2 {
+        self.size
+    }
+
+    /// The cell along the given edge of a room of the given size which the
+    /// given segment belongs to, as its offset from the top left cell.
+    pub fn segment_cell(size: IVec2, wall: u16, segment: i32) -> IVec2 {
+        match wall {
+            WALL_UP => IVec2::new(segment, 0),
+            WALL_RIGHT => IVec2::new(size.x - 1, -segment),
+            WALL_DOWN => IVec2::new(segment, 1 - size.y),
+            _ => IVec2::new(0, -segment),
+        }
+    }
+
+    /// Whether the room covers a single cell.
+    pub fn is_single_cell(&self) -> bool {
+        self.size == IVec2::ONE
+    }
+
+    /// Each covered cell, as its offset from the top left cell in grid
+    /// coordinates, where y goes up, along with its door code.
+    pub fn cells(&self) -> impl Iterator<Item = (IVec2, u16)> + '_ {
+        self.codes.iter().enumerate().map(|(index, code)| {
+            let index = index as i32;
+            (
+                IVec2::new(index % self.size.x, -(index / self.size.x)),
+                *code,
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::doors::ALL_WALLS;
+
+    #[test]
+    fn segments_close_the_outer_walls_of_their_cells() {
+        // A 2x2 arena with a door in the right half of its top edge, and one in
+        // the top half of its left edge.
+        let footprint = Footprint::from_segments(IVec2::new(2, 2), |wall, segment| {
+            !matches!((wall, segment), (WALL_UP, 1) | (WALL_LEFT, 0))
+        })
+        .unwrap();
+
+        let cells: Vec<_> = footprint.cells().collect();
+        assert_eq!(
+            cells,
+            [
+                (IVec2::new(0, 0), WALL_UP),
+                (IVec2::new(1, 0), WALL_RIGHT),
+                (IVec2::new(0, -1), WALL_DOWN | WALL_LEFT),
+                (IVec2::new(1, -1), WALL_RIGHT | WALL_DOWN),
+            ]
+        );
+    }
+
+    #[test]
+    fn single_cell_footprint_has_every_wall() {
+        let footprint = Footprint::from_segments(IVec2::ONE, |_, _| true).unwrap();
+
+        assert!(footprint.is_single_cell());
+        assert_eq!(
+            footprint.cells().collect::<Vec<_>>(),
+            [(IVec2::ZERO, ALL_WALLS)]
+        );
+    }
+
+    #[test]
+    fn footprints_are_limited_in_size() {
+        assert!(Footprint::from_segments(IVec2::new(3, 1), |_, _| true).is_none());
+        assert!(Footprint::from_segments(IVec2::new(1, 0), |_, _| true).is_none());
+        assert_eq!(Footprint::segments(IVec2::new(2, 1), WALL_UP), 2);
+        assert_eq!(Footprint::segments(IVec2::new(2, 1), WALL_LEFT), 1);
+    }
+}
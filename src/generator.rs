@@ -0,0 +1,170 @@
+//! The rules deciding which doors each new room in the dungeon gets.
+//!
+//! One generator is picked at startup with the `generator` launch option, so
+//! the different layouts can be compared in play:
+//! - `random`: [RandomGenerator], every wall is a coin toss.
+//! - `maze`: [MazeGenerator], rooms branch like a tree, without loops.
+//! - `corridors`: [CorridorGenerator], mostly long corridors and corners.
+
+use bevy::prelude::*;
+use tinyrand::Rand;
+
+use crate::doors::ALL_WALLS;
+use crate::layout::{
+    DungeonGrid, DungeonSeed, CELL_DOWN, CELL_LEFT, DOOR_STREAM, SIDES, WALL_DOWN, WALL_LEFT,
+    WALL_RIGHT, WALL_UP,
+};
+
+/// The names accepted by [by_name].
+pub const GENERATOR_NAMES: [&str; 3] = ["random", "maze", "corridors"];
+
+/// Picks the door code for new rooms as the dungeon is explored.
+pub trait DungeonGenerator: Send + Sync + 'static {
+    /// The name used to select this generator at startup.
+    fn name(&self) -> &'static str;
+
+    /// Pick the door code for a new room in `cell`, given every room placed in
+    /// the `grid` so far.
+    ///
+    /// Walls shared with placed neighbours are forced to match after this is
+    /// called, so a generator may ignore them. It only has to make sure any
+    /// decisions it cares about don't rely on them.
+    fn door_code(&self, seed: &DungeonSeed, cell: IVec2, grid: &DungeonGrid) -> u16;
+}
+
+/// Look up a generator by one of the [GENERATOR_NAMES].
+pub fn by_name(name: &str) -> Option<Box<dyn DungeonGenerator>> {
+    match name {
+        "random" => Some(Box::new(RandomGenerator)),
+        "maze" => Some(Box::new(MazeGenerator)),
+        "corridors" => Some(Box::new(CorridorGenerator)),
+        _ => None,
+    }
+}
+
+/// Every wall is picked at random, and neighbours are ignored.
+///
+/// A cell owns the walls on its north and east sides, and takes its south and
+/// west walls from the cells below and to the left of it. Two neighbours
+/// always agree on the wall between them, so the same seed gives the same
+/// dungeon whatever order the rooms are visited in.
+pub struct RandomGenerator;
+
+impl DungeonGenerator for RandomGenerator {
+    fn name(&self) -> &'static str {
+        "random"
+    }
+
+    fn door_code(&self, seed: &DungeonSeed, cell: IVec2, _grid: &DungeonGrid) -> u16 {
+        let own = seed.cell_rand(cell, DOOR_STREAM).next_lim_u16(15);
+        let below = seed
+            .cell_rand(cell + CELL_DOWN, DOOR_STREAM)
+            .next_lim_u16(15);
+        let left = seed
+            .cell_rand(cell + CELL_LEFT, DOOR_STREAM)
+            .next_lim_u16(15);
+
+        let mut code = own & (WALL_UP | WALL_RIGHT);
+
+        if below & WALL_UP != 0 {
+            code |= WALL_DOWN;
+        }
+
+        if left & WALL_RIGHT != 0 {
+            code |= WALL_LEFT;
+        }
+
+        code
+    }
+}
+
+/// Grows the dungeon as a spanning tree, so there's exactly one path between
+/// any two rooms.
+///
+/// A room only opens a door into an empty cell when no other placed room
+/// borders that cell. Each cell is then entered from a single room, which
+/// keeps loops from forming. The layout depends on the order rooms are
+/// visited in.
+pub struct MazeGenerator;
+
+impl DungeonGenerator for MazeGenerator {
+    fn name(&self) -> &'static str {
+        "maze"
+    }
+
+    fn door_code(&self, seed: &DungeonSeed, cell: IVec2, grid: &DungeonGrid) -> u16 {
+        let (mask, mut code) = grid.neighbours(cell).constraints();
+        let mut rand = seed.cell_rand(cell, DOOR_STREAM);
+
+        for (direction, wall, _) in SIDES {
+            if mask & wall != 0 {
+                continue;
+            }
+
+            let target = cell + direction;
+            let claimed = SIDES.iter().any(|(target_direction, _, _)| {
+                let other = target + *target_direction;
+                other != cell && grid.code(other).is_some()
+            });
+
+            if claimed || rand.next_lim_u16(2) == 0 {
+                code |= wall;
+            }
+        }
+
+        code
+    }
+}
+
+/// Prefers rooms with two doors, and straight corridors most of all, so the
+/// dungeon is made of long winding passages with the odd junction.
+pub struct CorridorGenerator;
+
+impl CorridorGenerator {
+    /// The relative chance of picking a room with the given door code.
+    fn weight(code: u16) -> u32 {
+        const CORRIDOR_HORIZONTAL: u16 = WALL_UP | WALL_DOWN;
+        const CORRIDOR_VERTICAL: u16 = WALL_RIGHT | WALL_LEFT;
+
+        match code {
+            CORRIDOR_HORIZONTAL | CORRIDOR_VERTICAL => 8,
+            // Corners
+            code if code.count_ones() == 2 => 4,
+            // Dead ends
+            code if code.count_ones() == 3 => 2,
+            // Junctions
+            _ => 1,
+        }
+    }
+}
+
+impl DungeonGenerator for CorridorGenerator {
+    fn name(&self) -> &'static str {
+        "corridors"
+    }
+
+    fn door_code(&self, seed: &DungeonSeed, cell: IVec2, grid: &DungeonGrid) -> u16 {
+        let (mask, walls) = grid.neighbours(cell).constraints();
+
+        // Candidate codes stop short of every wall closed, since a room is only
+        // ever placed where a neighbour has a door into it.
+        let candidates: Vec<u16> = (0..ALL_WALLS).filter(|code| code & mask == walls).collect();
+
+        let total_weight = candidates.iter().copied().map(Self::weight).sum();
+        if total_weight == 0 {
+            return walls;
+        }
+
+        let mut pick = seed.cell_rand(cell, DOOR_STREAM).next_lim_u32(total_weight);
+
+        for code in candidates {
+            let weight = Self::weight(code);
+            if pick < weight {
+                return code;
+            }
+            pick -= weight;
+        }
+
+        walls
+    }
+}
@@ -0,0 +1,1206 @@
+//! The grid the dungeon is laid out on, and the rules for placing rooms in it.
+//!
+//! Every room fills one grid cell, or a block of them, see
+//! [footprints](crate::footprints). Each cell's door code says which of its
+//! walls are closed, as a combination of [WALL_UP], [WALL_RIGHT], [WALL_DOWN],
+//! and [WALL_LEFT]. Two neighbouring cells must agree on the wall between them,
+//! see [doors](crate::doors).
+
+use std::collections::VecDeque;
+
+use bevy::platform::collections::{HashMap, HashSet};
+use bevy::prelude::*;
+use tinyrand::{Rand as _, Seeded as _, StdRand};
+
+use crate::doors::{self, Neighbours, ALL_WALLS};
+use crate::footprints::{Footprint, MAX_ROOM_SIZE};
+use crate::generator::DungeonGenerator;
+use crate::roles::{RoleCodes, RoomRole};
+
+pub use crate::doors::{WALL_DOWN, WALL_LEFT, WALL_RIGHT, WALL_UP};
+
+/// The width and height of a grid cell, in pixels. Levels fill a whole number
+/// of cells.
+pub const LEVEL_SIZE: f32 = 144.0;
+
+pub const CELL_UP: IVec2 = IVec2::new(0, 1);
+pub const CELL_RIGHT: IVec2 = IVec2::new(1, 0);
+pub const CELL_DOWN: IVec2 = IVec2::new(0, -1);
+pub const CELL_LEFT: IVec2 = IVec2::new(-1, 0);
+
+/// The four sides of a room: the direction of the neighbouring cell, the wall
+/// on this side, and the matching wall on the neighbour's side.
+pub const SIDES: [(IVec2, u16, u16); 4] = [
+    (CELL_UP, WALL_UP, WALL_DOWN),
+    (CELL_RIGHT, WALL_RIGHT, WALL_LEFT),
+    (CELL_DOWN, WALL_DOWN, WALL_UP),
+    (CELL_LEFT, WALL_LEFT, WALL_RIGHT),
+];
+
+/// The random number streams drawn from for each grid cell. See
+/// [DungeonSeed::cell_rand].
+pub const DOOR_STREAM: u64 = 0;
+pub const VARIANT_STREAM: u64 = 1;
+pub const FRONTIER_STREAM: u64 = 2;
+pub const STAIRS_STREAM: u64 = 3;
+pub const ROLE_STREAM: u64 = 4;
+pub const THEME_STREAM: u64 = 5;
+pub const FOOTPRINT_STREAM: u64 = 6;
+
+/// In a dungeon without bounds, rooms at least this many cells from the start
+/// hall, measured per axis, may have stairs down.
+pub const STAIRS_MIN_DISTANCE: i32 = 3;
+
+/// In a dungeon without bounds, one in this many rooms far enough from the
+/// start hall has stairs down.
+pub const STAIRS_CHANCE: u32 = 12;
+
+/// One in this many new rooms spans several cells, if there is a room spanning
+/// several cells which fits. See [DungeonGrid::choose_footprint].
+pub const FOOTPRINT_CHANCE: u32 = 4;
+
+/// The seed every level layout decision is derived from.
+///
+/// Read from the `seed` launch option, see
+/// [launch_option](crate::launch_option). If it's not given, a seed is picked
+/// from the clock and shown in the banner so a run can be reproduced later.
+#[derive(Resource, Clone, Copy, Debug, Deref)]
+pub struct DungeonSeed(pub u64);
+
+impl DungeonSeed {
+    /// The seed for the floor at the given [Depth]. The first floor uses the
+    /// seed as it is, so seeds from before there were floors still give the
+    /// same dungeon.
+    pub fn floor(&self, depth: u32) -> Self {
+        Self(self.0 ^ (depth as u64).wrapping_mul(0xD6E8_FEB8_6659_FD93))
+    }
+
+    /// Look for a seed in the launch options, falling back to the clock.
+    pub fn from_env() -> Self {
+        if let Some(value) = crate::launch_option("seed") {
+            match value.parse() {
+                Ok(seed) => return Self(seed),
+                Err(_) => warn!("Ignoring bad seed: {value}"),
+            }
+        }
+
+        #[cfg(not(target_arch = "wasm32"))]
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as u64;
+
+        #[cfg(target_arch = "wasm32")]
+        let now = js_sys::Date::now() as u64;
+
+        Self(now)
+    }
+
+    /// A random number generator for the given grid cell.
+    ///
+    /// Each cell gets its own streams, so what we draw for a cell never depends
+    /// on how many draws were made for other cells before it. Separate streams
+    /// are used for separate decisions, like [DOOR_STREAM] and
+    /// [VARIANT_STREAM], so adding a decision doesn't change earlier ones.
+    pub fn cell_rand(&self, cell: IVec2, stream: u64) -> StdRand {
+        // splitmix64 finalizer, to spread neighbouring cells far apart.
+        let mut hash = self.0
+            ^ (cell.x as u32 as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15)
+            ^ (cell.y as u32 as u64).wrapping_mul(0xC2B2_AE3D_27D4_EB4F)
+            ^ stream.wrapping_mul(0x1656_67B1_9E37_79F9);
+        hash = (hash ^ (hash >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        hash = (hash ^ (hash >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        hash ^= hash >> 31;
+
+        StdRand::seed(hash)
+    }
+}
+
+/// How many floors down the player is, starting at 0 for the floor they start
+/// on. Each floor has its own layout, from [DungeonSeed::floor].
+#[derive(Resource, Clone, Copy, Debug, Default, Deref, DerefMut)]
+pub struct Depth(pub u32);
+
+/// The level spawned for a room in the [DungeonGrid].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CellLevel {
+    /// No level is spawned, because it was unloaded when the player wandered
+    /// off, or because the layout was generated without a game running.
+    Unloaded,
+    /// The level has been spawned, but its asset hasn't loaded yet, so it has
+    /// no world bounds. The cell is reserved, so nothing else is spawned on
+    /// top of it in the meantime.
+    Pending(Entity),
+    /// The level is spawned and fully loaded.
+    Loaded(Entity),
+}
+
+impl CellLevel {
+    /// The level entity, if one is spawned, whether or not it has loaded.
+    pub fn entity(self) -> Option<Entity> {
+        match self {
+            CellLevel::Unloaded => None,
+            CellLevel::Pending(level) | CellLevel::Loaded(level) => Some(level),
+        }
+    }
+}
+
+/// A cell of a room which has been placed in the [DungeonGrid].
+#[derive(Clone, Copy, Debug)]
+pub struct GridCell {
+    /// Which walls of the cell are closed, as a combination of [WALL_UP],
+    /// [WALL_RIGHT], [WALL_DOWN], and [WALL_LEFT].
+    pub code: u16,
+    /// The top left cell of the room, where its level is spawned. This is the
+    /// cell itself, unless the room spans several cells.
+    pub anchor: IVec2,
+    /// The iid of the [RoomTemplate](crate::room_templates::RoomTemplate)
+    /// picked for this room, or `None` if the layout was generated without
+    /// room templates.
+    pub template: Option<u128>,
+    /// The special role of this room, or `None` for a plain room.
+    pub role: Option<RoomRole>,
+    /// The level spawned for this room, if any. Shared by every cell of a
+    /// room spanning several cells.
+    pub level: CellLevel,
+}
+
+/// The layout of the dungeon: every room placed so far, keyed by grid cell.
+///
+/// This is the single source of truth for which rooms exist and how their
+/// doors line up. A cell stays in the grid after its level is unloaded, so the
+/// same room comes back when the player returns.
+///
+/// Rooms should be added with [DungeonGrid::place], which keeps the frontier
+/// up to date.
+#[derive(Resource, Default, Deref, DerefMut)]
+pub struct DungeonGrid {
+    #[deref]
+    pub cells: HashMap<IVec2, GridCell>,
+    /// The empty cells which a placed room has a door into. As long as this
+    /// isn't empty, there's more dungeon to explore.
+    pub frontier: HashSet<IVec2>,
+    /// The rectangle of cells rooms may be placed in, inclusive of its edges,
+    /// or `None` if the dungeon grows forever.
+    pub bounds: Option<IRect>,
+    /// The door codes there are special rooms for. Until set, no room is
+    /// given a role.
+    pub role_codes: RoleCodes,
+    /// The rooms spanning several cells there are room templates for. Until
+    /// set, every room fills a single cell.
+    pub footprints: Vec<Footprint>,
+}
+
+impl DungeonGrid {
+    /// An empty grid whose rooms all fit in the given rectangle of cells.
+    ///
+    /// Rooms on the edge of the rectangle are walled on the outside, as if
+    /// every cell beyond it held a room with no doors.
+    pub fn bounded(bounds: IRect) -> Self {
+        Self {
+            bounds: Some(bounds),
+            ..Default::default()
+        }
+    }
+
+    /// An empty grid, bounded if the `bounds` launch option is given, see
+    /// [parse_bounds].
+    pub fn from_env() -> Self {
+        if let Some(value) = crate::launch_option("bounds") {
+            match parse_bounds(&value) {
+                Some(bounds) => return Self::bounded(bounds),
+                None => {
+                    warn!("Ignoring bad bounds: {value}. Expected <width>x<height>, at least 3x3")
+                }
+            }
+        }
+
+        Self::default()
+    }
+
+    /// An empty grid with the same bounds, special rooms, and rooms spanning
+    /// several cells, for the next floor down.
+    pub fn next_floor(&self) -> Self {
+        Self {
+            bounds: self.bounds,
+            role_codes: self.role_codes,
+            footprints: self.footprints.clone(),
+            ..Default::default()
+        }
+    }
+
+    /// Whether rooms may be placed in the given cell.
+    pub fn in_bounds(&self, cell: IVec2) -> bool {
+        self.bounds.is_none_or(|bounds| bounds.contains(cell))
+    }
+
+    /// Add a room to the grid, replacing whatever was in the cell before.
+    pub fn place(&mut self, cell: IVec2, grid_cell: GridCell) {
+        self.frontier.remove(&cell);
+
+        for (direction, wall, _) in SIDES {
+            let neighbour = cell + direction;
+            if grid_cell.code & wall == 0
+                && !self.cells.contains_key(&neighbour)
+                && self.in_bounds(neighbour)
+            {
+                self.frontier.insert(neighbour);
+            }
+        }
+
+        self.cells.insert(cell, grid_cell);
+    }
+
+    /// Add the start hall to the grid. It sits in the origin cell, with a door
+    /// on every side.
+    pub fn place_start_hall(&mut self, template: Option<u128>, level: CellLevel) {
+        self.place(
+            IVec2::ZERO,
+            GridCell {
+                code: 0,
+                anchor: IVec2::ZERO,
+                template,
+                role: None,
+                level,
+            },
+        );
+    }
+
+    /// Add a room spanning several cells to the grid, with its top left cell
+    /// in `anchor`. Every cell it covers shares the same template and level.
+    pub fn place_room(
+        &mut self,
+        anchor: IVec2,
+        footprint: &Footprint,
+        template: Option<u128>,
+        level: CellLevel,
+    ) {
+        for (offset, code) in footprint.cells() {
+            self.place(
+                anchor + offset,
+                GridCell {
+                    code,
+                    anchor,
+                    template,
+                    role: None,
+                    level,
+                },
+            );
+        }
+    }
+
+    /// Every cell of the room whose top left cell is `anchor`.
+    pub fn room_cells(&self, anchor: IVec2) -> impl Iterator<Item = IVec2> + '_ {
+        (0..MAX_ROOM_SIZE.y)
+            .flat_map(move |y| (0..MAX_ROOM_SIZE.x).map(move |x| anchor + IVec2::new(x, -y)))
+            .filter(move |cell| {
+                self.get(cell)
+                    .is_some_and(|grid_cell| grid_cell.anchor == anchor)
+            })
+    }
+
+    /// Whether the room in the given cell spans several cells.
+    pub fn spans_cells(&self, cell: IVec2) -> bool {
+        self.get(&cell)
+            .is_some_and(|grid_cell| self.room_cells(grid_cell.anchor).nth(1).is_some())
+    }
+
+    /// The footprint of the room whose top left cell is `anchor`, read back
+    /// from the door codes of its cells.
+    pub fn room_footprint(&self, anchor: IVec2) -> Option<Footprint> {
+        let size = self.room_cells(anchor).fold(IVec2::ZERO, |size, cell| {
+            size.max((cell - anchor) * IVec2::new(1, -1) + 1)
+        });
+
+        Footprint::from_segments(size, |wall, segment| {
+            self.code(anchor + Footprint::segment_cell(size, wall, segment))
+                .is_some_and(|code| code & wall != 0)
+        })
+    }
+
+    /// Open the given wall of a placed room, adding the cell behind it to the
+    /// frontier if it's empty. The room's level isn't touched, so this is only
+    /// meant for rooms which haven't been spawned yet. Rooms spanning several
+    /// cells are never opened, as there'd be no room template with their new
+    /// doors.
+    fn open_wall(&mut self, cell: IVec2, direction: IVec2, wall: u16) {
+        if let Some(grid_cell) = self.cells.get_mut(&cell) {
+            grid_cell.code &= !wall;
+
+            if !self.cells.contains_key(&(cell + direction)) {
+                self.frontier.insert(cell + direction);
+            }
+        }
+    }
+
+    /// Whether placing a room with the given code in `cell` would leave no
+    /// doors leading out into the open space around the dungeon.
+    ///
+    /// A door into a pocket of empty cells walled in by rooms isn't enough, as
+    /// the pocket is filled in sooner or later, and the dungeon ends there. See
+    /// [DungeonGrid::any_outside].
+    pub fn would_seal(&self, cell: IVec2, code: u16) -> bool {
+        let ways_on: Vec<IVec2> = self
+            .frontier
+            .iter()
+            .copied()
+            .filter(|frontier| *frontier != cell)
+            .chain(
+                SIDES
+                    .iter()
+                    .filter(|(direction, wall, _)| {
+                        code & wall == 0 && !self.cells.contains_key(&(cell + *direction))
+                    })
+                    .map(|(direction, _, _)| cell + *direction),
+            )
+            .collect();
+
+        !self.any_outside(&ways_on, &[cell])
+    }
+
+    /// Whether any of the given empty cells is connected to the open space
+    /// around the dungeon, through other empty cells, if the `filled` cells
+    /// were filled too.
+    fn any_outside(&self, cells: &[IVec2], filled: &[IVec2]) -> bool {
+        // The area around every room, and the cells to fill, one cell wider
+        // on each side. Its edge is empty, and all of it is outside.
+        let Some(area) = self
+            .cells
+            .keys()
+            .chain(filled)
+            .map(|cell| IRect::from_corners(*cell, *cell))
+            .reduce(|area, cell| area.union(cell))
+            .map(|area| area.inflate(1))
+        else {
+            return !cells.is_empty();
+        };
+
+        let on_edge = |cell: IVec2| {
+            cell.x == area.min.x
+                || cell.x == area.max.x
+                || cell.y == area.min.y
+                || cell.y == area.max.y
+        };
+
+        if cells.iter().any(|cell| on_edge(*cell)) {
+            return true;
+        }
+
+        let is_empty = |cell: IVec2| !self.cells.contains_key(&cell) && !filled.contains(&cell);
+
+        // Otherwise flood the empty cells in from the edge, until one of the
+        // given cells is reached.
+        let mut outside = HashSet::from([area.min]);
+        let mut to_visit = vec![area.min];
+
+        while let Some(cell) = to_visit.pop() {
+            for (direction, _, _) in SIDES {
+                let neighbour = cell + direction;
+
+                if area.contains(neighbour) && is_empty(neighbour) && outside.insert(neighbour) {
+                    if cells.contains(&neighbour) {
+                        return true;
+                    }
+
+                    to_visit.push(neighbour);
+                }
+            }
+        }
+
+        false
+    }
+
+    /// Whether a room with the given footprint may be placed with its top left
+    /// cell in `anchor`.
+    ///
+    /// Every cell it covers must be empty, in bounds, and agree with the rooms
+    /// around it. In a dungeon without bounds, the room mustn't seal the
+    /// dungeon off either, see [DungeonGrid::would_seal].
+    pub fn fits(&self, anchor: IVec2, footprint: &Footprint) -> bool {
+        let covered: Vec<IVec2> = footprint
+            .cells()
+            .map(|(offset, _)| anchor + offset)
+            .collect();
+
+        let fits = footprint.cells().all(|(offset, code)| {
+            let cell = anchor + offset;
+            !self.contains_key(&cell) && self.in_bounds(cell) && self.neighbours(cell).agrees(code)
+        });
+
+        if !fits || self.bounds.is_some() {
+            return fits;
+        }
+
+        let ways_on: Vec<IVec2> = self
+            .frontier
+            .iter()
+            .copied()
+            .filter(|frontier| !covered.contains(frontier))
+            .chain(footprint.cells().flat_map(|(offset, code)| {
+                SIDES
+                    .iter()
+                    .filter(move |(_, wall, _)| code & wall == 0)
+                    .map(move |(direction, _, _)| anchor + offset + *direction)
+            }))
+            .filter(|cell| !covered.contains(cell) && !self.cells.contains_key(cell))
+            .collect();
+
+        self.any_outside(&ways_on, &covered)
+    }
+
+    /// The door code of the room in the given cell, if one has been placed.
+    pub fn code(&self, cell: IVec2) -> Option<u16> {
+        self.get(&cell).map(|grid_cell| grid_cell.code)
+    }
+
+    /// Whether the room in the given cell has stairs down to the next floor.
+    ///
+    /// Exit rooms always have stairs. Besides those, a bounded dungeon has a
+    /// single flight of stairs, in one of the corners of its bounds with a
+    /// room in it, since every room in them can be reached. Otherwise any room
+    /// far enough from the start hall may have stairs, see [STAIRS_CHANCE].
+    pub fn has_stairs(&self, seed: &DungeonSeed, cell: IVec2) -> bool {
+        if self
+            .get(&cell)
+            .is_some_and(|grid_cell| grid_cell.role == Some(RoomRole::Exit))
+        {
+            return true;
+        }
+
+        match self.bounds {
+            Some(bounds) => {
+                // A corner may be left empty when only rooms spanning several
+                // cells border it.
+                let corners: Vec<IVec2> = [
+                    bounds.min,
+                    IVec2::new(bounds.max.x, bounds.min.y),
+                    IVec2::new(bounds.min.x, bounds.max.y),
+                    bounds.max,
+                ]
+                .into_iter()
+                .filter(|corner| self.contains_key(corner))
+                .collect();
+
+                if corners.is_empty() {
+                    return false;
+                }
+
+                let pick = seed
+                    .cell_rand(IVec2::ZERO, STAIRS_STREAM)
+                    .next_lim_usize(corners.len());
+
+                cell == corners[pick]
+            }
+            None => {
+                cell.abs().max_element() >= STAIRS_MIN_DISTANCE
+                    && seed
+                        .cell_rand(cell, STAIRS_STREAM)
+                        .next_lim_u32(STAIRS_CHANCE)
+                        == 0
+            }
+        }
+    }
+
+    /// Pick the special role, if any, for a new room with the given door code
+    /// in `cell`.
+    ///
+    /// The [RoleRule](crate::roles::RoleRule) of each role is checked in
+    /// turn, and the first role which allows the cell, has a room with the
+    /// right doors, and wins its chance, is given to the room.
+    pub fn choose_role(&self, seed: &DungeonSeed, cell: IVec2, code: u16) -> Option<RoomRole> {
+        let distance = cell.abs().max_element();
+        let mut rand = seed.cell_rand(cell, ROLE_STREAM);
+
+        RoomRole::ALL.into_iter().find(|role| {
+            let rule = role.rule();
+            let placed = self
+                .values()
+                .filter(|grid_cell| grid_cell.role == Some(*role))
+                .count();
+
+            rule.allows(distance, code, placed)
+                && self.role_codes.contains(*role, code)
+                && rand.next_lim_u32(rule.chance) == 0
+        })
+    }
+
+    /// Pick a room spanning several cells to cover the empty `cell`, if any.
+    /// Returns the top left cell of the room, along with its footprint.
+    ///
+    /// One in [FOOTPRINT_CHANCE] new rooms is picked from every way of
+    /// covering `cell` with one of the [footprints](DungeonGrid::footprints)
+    /// which [fits](DungeonGrid::fits). Rooms spanning several cells never have
+    /// a role.
+    pub fn choose_footprint(&self, seed: &DungeonSeed, cell: IVec2) -> Option<(IVec2, &Footprint)> {
+        if self.footprints.is_empty() {
+            return None;
+        }
+
+        let mut rand = seed.cell_rand(cell, FOOTPRINT_STREAM);
+        if rand.next_lim_u32(FOOTPRINT_CHANCE) != 0 {
+            return None;
+        }
+
+        let placements: Vec<(IVec2, &Footprint)> = self
+            .footprints
+            .iter()
+            .flat_map(|footprint| {
+                footprint
+                    .cells()
+                    .map(move |(offset, _)| (cell - offset, footprint))
+            })
+            .filter(|(anchor, footprint)| self.fits(*anchor, footprint))
+            .collect();
+
+        if placements.is_empty() {
+            return None;
+        }
+
+        Some(placements[rand.next_lim_usize(placements.len())])
+    }
+
+    /// The door codes of the rooms around the given cell. Cells outside the
+    /// bounds count as rooms with every wall closed.
+    pub fn neighbours(&self, cell: IVec2) -> Neighbours {
+        let code = |neighbour: IVec2| {
+            if self.in_bounds(neighbour) {
+                self.code(neighbour)
+            } else {
+                Some(ALL_WALLS)
+            }
+        };
+
+        Neighbours {
+            up: code(cell + CELL_UP),
+            right: code(cell + CELL_RIGHT),
+            down: code(cell + CELL_DOWN),
+            left: code(cell + CELL_LEFT),
+        }
+    }
+
+    /// The level entity in the given cell, if one is currently spawned or
+    /// pending.
+    pub fn level(&self, cell: IVec2) -> Option<Entity> {
+        self.get(&cell)
+            .and_then(|grid_cell| grid_cell.level.entity())
+    }
+
+    /// Whether the level in the given cell has finished loading.
+    pub fn is_loaded(&self, cell: IVec2) -> bool {
+        self.get(&cell)
+            .is_some_and(|grid_cell| matches!(grid_cell.level, CellLevel::Loaded(_)))
+    }
+
+    /// Pick the door code for a new room in `cell`. Used by the game and the
+    /// tools alike, so they lay out the same dungeon from the same seed.
+    ///
+    /// The `generator` makes the first pick. Walls shared with rooms already
+    /// placed around the cell, or with the edge of the bounds, are then forced
+    /// to match. In a dungeon without bounds, if the room would seal it off,
+    /// one of its walls facing the open space around it is opened. Bounded dungeons are
+    /// instead connected up by [DungeonGrid::explore].
+    pub fn choose_code(
+        &self,
+        seed: &DungeonSeed,
+        generator: &dyn DungeonGenerator,
+        cell: IVec2,
+    ) -> u16 {
+        // Whatever the generator picked, the walls shared with rooms which
+        // already exist are forced to match them.
+        let mut rand = doors::constrain(
+            generator.door_code(seed, cell, self),
+            &self.neighbours(cell),
+        );
+
+        // Never close off the last way on. If this room would leave no door
+        // out into the open space around the dungeon, open one of its walls
+        // which faces it. A bounded dungeon is meant to end.
+        if self.bounds.is_none() && self.would_seal(cell, rand) {
+            let open_walls: Vec<u16> = SIDES
+                .iter()
+                .filter(|(direction, _, _)| {
+                    let neighbour = cell + *direction;
+                    self.code(neighbour).is_none() && self.any_outside(&[neighbour], &[cell])
+                })
+                .map(|(_, wall, _)| *wall)
+                .collect();
+
+            if open_walls.is_empty() {
+                warn!("The dungeon has sealed itself off at: {cell}");
+            } else {
+                let pick = seed
+                    .cell_rand(cell, FRONTIER_STREAM)
+                    .next_lim_usize(open_walls.len());
+                rand &= !open_walls[pick];
+            }
+        }
+
+        rand
+    }
+}
+
+/// Read the `<width>x<height>` of a bounded dungeon, and turn it into a
+/// rectangle of cells centred on the start hall.
+///
+/// Both sides must be at least 3 cells long, so the start hall has a room
+/// behind each of its doors.
+pub fn parse_bounds(value: &str) -> Option<IRect> {
+    let (width, height) = value.split_once('x')?;
+    let size = IVec2::new(width.parse().ok()?, height.parse().ok()?);
+
+    if size.min_element() < 3 {
+        return None;
+    }
+
+    let min = -(size - 1) / 2;
+    Some(IRect::from_corners(min, min + size - 1))
+}
+
+/// How far [DungeonGrid::explore] goes before it stops.
+#[derive(Clone, Copy, Debug)]
+pub enum ExploreLimit {
+    /// Place every reachable room within this many cells of the start hall,
+    /// measured per axis.
+    Radius(i32),
+    /// Stop once this many rooms, including the start hall, are placed.
+    Rooms(usize),
+}
+
+impl ExploreLimit {
+    /// Whether a room may be placed in `cell` of the `grid`.
+    fn allows(self, grid: &DungeonGrid, cell: IVec2) -> bool {
+        match self {
+            ExploreLimit::Radius(radius) => cell.abs().max_element() <= radius,
+            ExploreLimit::Rooms(rooms) => grid.len() < rooms,
+        }
+    }
+}
+
+impl DungeonGrid {
+    /// Lay out the dungeon without a game running, as if the player visited
+    /// every room they could reach, breadth first from the start hall. The
+    /// start hall is placed first if it isn't there yet. Rooms already in the
+    /// grid, like [pinned](crate::pins) rooms, are kept as they are.
+    ///
+    /// Like walking into a level in the game, visiting a room places a new
+    /// room in every empty cell it has a door into. Generators which look at
+    /// the rooms around a cell may lay out a different dungeon when the game
+    /// visits rooms in another order.
+    ///
+    /// In a bounded dungeon, whenever no door leads on but cells are left
+    /// empty, a wall between a placed room and an empty cell is opened. With
+    /// a large enough limit, every cell in the bounds is filled and reachable.
+    pub fn explore(
+        &mut self,
+        seed: &DungeonSeed,
+        generator: &dyn DungeonGenerator,
+        limit: ExploreLimit,
+    ) {
+        if !self.contains_key(&IVec2::ZERO) {
+            self.place_start_hall(None, CellLevel::Unloaded);
+        }
+
+        let mut to_visit = VecDeque::from([IVec2::ZERO]);
+        let mut visited = HashSet::from([IVec2::ZERO]);
+
+        loop {
+            while let Some(visit) = to_visit.pop_front() {
+                let Some(visit_code) = self.code(visit) else {
+                    continue;
+                };
+
+                for (direction, wall, _) in SIDES {
+                    let cell = visit + direction;
+
+                    if visit_code & wall != 0 || !self.in_bounds(cell) {
+                        continue;
+                    }
+
+                    // Rooms placed before exploring, like pinned rooms, are
+                    // visited once they're reached.
+                    if self.contains_key(&cell) {
+                        if visited.insert(cell) {
+                            to_visit.push_back(cell);
+                        }
+                        continue;
+                    }
+
+                    if !limit.allows(self, cell) {
+                        continue;
+                    }
+
+                    // A room spanning several cells only fits if the limit
+                    // allows every cell of it.
+                    if let Some((anchor, footprint)) = self
+                        .choose_footprint(seed, cell)
+                        .filter(|(anchor, footprint)| {
+                            footprint
+                                .cells()
+                                .all(|(offset, _)| limit.allows(self, *anchor + offset))
+                        })
+                        .map(|(anchor, footprint)| (anchor, footprint.clone()))
+                    {
+                        self.place_room(anchor, &footprint, None, CellLevel::Unloaded);
+                        for (offset, _) in footprint.cells() {
+                            visited.insert(anchor + offset);
+                            to_visit.push_back(anchor + offset);
+                        }
+                        continue;
+                    }
+
+                    let code = self.choose_code(seed, generator, cell);
+                    let role = self.choose_role(seed, cell, code);
+                    self.place(
+                        cell,
+                        GridCell {
+                            code,
+                            anchor: cell,
+                            template: None,
+                            role,
+                            level: CellLevel::Unloaded,
+                        },
+                    );
+
+                    visited.insert(cell);
+                    to_visit.push_back(cell);
+                }
+            }
+
+            match self.connect_unreached(seed, limit) {
+                Some(visit) => to_visit.push_back(visit),
+                None => break,
+            }
+        }
+    }
+
+    /// In a bounded dungeon, open a door from a placed room into the first
+    /// empty cell next to one, scanning from the top left of the bounds.
+    /// Returns the room to visit again, or `None` if there's nothing left to
+    /// connect.
+    ///
+    /// Only rooms filling a single cell, without a template or role, are
+    /// opened, so an empty cell bordered by nothing but other rooms is left
+    /// empty.
+    fn connect_unreached(&mut self, seed: &DungeonSeed, limit: ExploreLimit) -> Option<IVec2> {
+        let bounds = self.bounds?;
+
+        let cell = (bounds.min.y..=bounds.max.y)
+            .rev()
+            .flat_map(|y| (bounds.min.x..=bounds.max.x).map(move |x| IVec2::new(x, y)))
+            .filter(|cell| !self.contains_key(cell) && limit.allows(self, *cell))
+            .find(|cell| {
+                SIDES
+                    .iter()
+                    .any(|(direction, _, _)| self.can_open(*cell + *direction))
+            })?;
+
+        // The walls of placed rooms facing the empty cell, as seen from those
+        // rooms.
+        let placed: Vec<(IVec2, IVec2, u16)> = SIDES
+            .iter()
+            .filter(|(direction, _, _)| self.can_open(cell + *direction))
+            .map(|(direction, _, opposite_wall)| (cell + *direction, -*direction, *opposite_wall))
+            .collect();
+
+        let pick = seed
+            .cell_rand(cell, FRONTIER_STREAM)
+            .next_lim_usize(placed.len());
+        let (room, direction, wall) = placed[pick];
+
+        self.open_wall(room, direction, wall);
+
+        Some(room)
+    }
+
+    /// Whether there's a room filling only the given cell, whose walls may be
+    /// opened by [DungeonGrid::connect_unreached]. Rooms whose template has
+    /// already been picked, like pinned rooms, keep their doors, as do rooms
+    /// with a role, which may only have rooms with the doors they were given.
+    fn can_open(&self, cell: IVec2) -> bool {
+        self.get(&cell)
+            .is_some_and(|grid_cell| grid_cell.template.is_none() && grid_cell.role.is_none())
+            && !self.spans_cells(cell)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tinyrand::Rand as _;
+
+    use super::*;
+    use crate::generator::{by_name, GENERATOR_NAMES};
+    use crate::room_templates::RoomTemplates;
+
+    const PROJECT: &str = include_str!("../assets/ldtk/dungeon_of_madness.ldtk");
+
+    /// Every pair of neighbouring rooms agrees on the wall between them.
+    fn assert_consistent(grid: &DungeonGrid) {
+        for (cell, grid_cell) in grid.iter() {
+            assert!(
+                grid.neighbours(*cell).agrees(grid_cell.code),
+                "room at {cell} with code {:#x} disagrees with {:?}",
+                grid_cell.code,
+                grid.neighbours(*cell),
+            );
+        }
+    }
+
+    #[test]
+    fn explored_rooms_agree_with_their_neighbours() {
+        for name in GENERATOR_NAMES {
+            let generator = by_name(name).unwrap();
+
+            for seed in 0..200 {
+                let mut grid = DungeonGrid::default();
+                grid.explore(&DungeonSeed(seed), &*generator, ExploreLimit::Radius(6));
+                assert_consistent(&grid);
+            }
+        }
+    }
+
+    #[test]
+    fn chosen_code_agrees_whatever_the_visiting_order() {
+        // Place rooms in a scattered order, rather than breadth first, so cells
+        // are often boxed in by neighbours on several sides.
+        for name in GENERATOR_NAMES {
+            let generator = by_name(name).unwrap();
+
+            for seed in 0..50 {
+                let seed = DungeonSeed(seed);
+                let mut grid = DungeonGrid::default();
+                let mut order = seed.cell_rand(IVec2::ZERO, 0);
+
+                for _ in 0..300 {
+                    let cell = IVec2::new(
+                        order.next_lim_u32(13) as i32 - 6,
+                        order.next_lim_u32(13) as i32 - 6,
+                    );
+
+                    if grid.contains_key(&cell) {
+                        continue;
+                    }
+
+                    let code = grid.choose_code(&seed, &*generator, cell);
+                    assert!(grid.neighbours(cell).agrees(code));
+
+                    grid.place(
+                        cell,
+                        GridCell {
+                            code,
+                            anchor: cell,
+                            template: None,
+                            role: None,
+                            level: CellLevel::Unloaded,
+                        },
+                    );
+                }
+
+                assert_consistent(&grid);
+            }
+        }
+    }
+
+    #[test]
+    fn explore_is_reproducible() {
+        for name in GENERATOR_NAMES {
+            let generator = by_name(name).unwrap();
+            let seed = DungeonSeed(1234);
+
+            let mut first = DungeonGrid::default();
+            first.explore(&seed, &*generator, ExploreLimit::Rooms(60));
+            let mut second = DungeonGrid::default();
+            second.explore(&seed, &*generator, ExploreLimit::Rooms(60));
+
+            assert_eq!(first.len(), second.len());
+            for (cell, grid_cell) in first.iter() {
+                assert_eq!(second.code(*cell), Some(grid_cell.code));
+            }
+        }
+    }
+
+    #[test]
+    fn bounded_dungeon_is_closed_and_fully_explorable() {
+        let bounds = parse_bounds("7x5").unwrap();
+        assert_eq!(bounds, IRect::new(-3, -2, 3, 2));
+
+        for name in GENERATOR_NAMES {
+            let generator = by_name(name).unwrap();
+
+            for seed in 0..100 {
+                let mut grid = DungeonGrid::bounded(bounds);
+                grid.explore(
+                    &DungeonSeed(seed),
+                    &*generator,
+                    ExploreLimit::Rooms(usize::MAX),
+                );
+
+                assert_eq!(grid.len(), 35);
+                assert_consistent(&grid);
+
+                // Walk through the doors from the start hall, without ever
+                // leaving the bounds.
+                let mut reached = HashSet::from([IVec2::ZERO]);
+                let mut to_visit = vec![IVec2::ZERO];
+
+                while let Some(cell) = to_visit.pop() {
+                    let code = grid.code(cell).unwrap();
+
+                    for (direction, wall, _) in SIDES {
+                        if code & wall == 0 {
+                            let neighbour = cell + direction;
+                            assert!(bounds.contains(neighbour), "door out of bounds at {cell}");
+
+                            if reached.insert(neighbour) {
+                                to_visit.push(neighbour);
+                            }
+                        }
+                    }
+                }
+
+                assert_eq!(reached.len(), 35, "seed {seed} with {name}");
+            }
+        }
+    }
+
+    #[test]
+    fn unbounded_dungeon_is_never_sealed() {
+        for name in GENERATOR_NAMES {
+            let generator = by_name(name).unwrap();
+
+            for seed in 0..50 {
+                for rooms in [1, 2, 3, 5, 10, 20, 40] {
+                    let mut grid = DungeonGrid::default();
+                    grid.explore(&DungeonSeed(seed), &*generator, ExploreLimit::Rooms(rooms));
+
+                    assert_eq!(grid.len(), rooms, "seed {seed} with {name}");
+                    assert!(
+                        !grid.frontier.is_empty(),
+                        "seed {seed} with {name} sealed after {rooms} rooms"
+                    );
+
+                    // Every cell of the frontier is empty, behind an open
+                    // door.
+                    for cell in &grid.frontier {
+                        assert!(!grid.contains_key(cell));
+                        assert!(SIDES.iter().any(|(direction, _, opposite_wall)| {
+                            grid.code(*cell + *direction)
+                                .is_some_and(|code| code & opposite_wall == 0)
+                        }));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn parse_bounds_rejects_small_or_malformed_sizes() {
+        assert_eq!(parse_bounds("2x5"), None);
+        assert_eq!(parse_bounds("5"), None);
+        assert_eq!(parse_bounds("ax5"), None);
+        assert_eq!(parse_bounds("4x4"), Some(IRect::new(-1, -1, 2, 2)));
+    }
+
+    #[test]
+    fn bounded_dungeon_has_one_flight_of_stairs_per_floor() {
+        let bounds = parse_bounds("5x5").unwrap();
+        let generator = by_name("random").unwrap();
+
+        for depth in 0..20 {
+            let seed = DungeonSeed(42).floor(depth);
+            let mut grid = DungeonGrid::bounded(bounds);
+            grid.explore(&seed, &*generator, ExploreLimit::Rooms(usize::MAX));
+
+            let stairs = grid
+                .keys()
+                .filter(|cell| grid.has_stairs(&seed, **cell))
+                .count();
+            assert_eq!(stairs, 1);
+        }
+    }
+
+    #[test]
+    fn first_floor_keeps_the_seed() {
+        assert_eq!(*DungeonSeed(1234).floor(0), 1234);
+        assert_ne!(*DungeonSeed(1234).floor(1), 1234);
+    }
+
+    #[test]
+    fn special_rooms_follow_their_rules() {
+        let generator = by_name("random").unwrap();
+        let mut placed_any = false;
+
+        for seed in 0..50 {
+            let mut grid = DungeonGrid {
+                role_codes: RoleCodes::all(),
+                ..Default::default()
+            };
+            grid.explore(&DungeonSeed(seed), &*generator, ExploreLimit::Radius(12));
+
+            for role in RoomRole::ALL {
+                let rule = role.rule();
+                let rooms: Vec<_> = grid
+                    .iter()
+                    .filter(|(_, grid_cell)| grid_cell.role == Some(role))
+                    .collect();
+
+                assert!(rooms.len() <= rule.max_per_floor);
+                placed_any |= !rooms.is_empty();
+
+                for (cell, grid_cell) in rooms {
+                    assert!(cell.abs().max_element() >= rule.min_distance);
+                    assert!(!rule.dead_end_only || crate::roles::is_dead_end(grid_cell.code));
+                }
+            }
+        }
+
+        assert!(placed_any);
+    }
+
+    #[test]
+    fn bounded_special_rooms_keep_doors_with_a_room() {
+        let (room_templates, _) = RoomTemplates::from_project_json(PROJECT.as_bytes()).unwrap();
+        let role_codes = room_templates.role_codes();
+        let mut placed_any = false;
+
+        for name in GENERATOR_NAMES {
+            let generator = by_name(name).unwrap();
+
+            for seed in 0..50 {
+                let mut grid = DungeonGrid {
+                    role_codes,
+                    footprints: room_templates.footprints(),
+                    ..DungeonGrid::bounded(parse_bounds("9x9").unwrap())
+                };
+                grid.explore(
+                    &DungeonSeed(seed),
+                    &*generator,
+                    ExploreLimit::Rooms(usize::MAX),
+                );
+
+                for (cell, grid_cell) in grid.iter() {
+                    if let Some(role) = grid_cell.role {
+                        placed_any = true;
+                        assert!(
+                            role_codes.contains(role, grid_cell.code),
+                            "{role} room at {cell} with code {:#x}, seed {seed} with {name}",
+                            grid_cell.code,
+                        );
+                    }
+                }
+            }
+        }
+
+        assert!(placed_any);
+    }
+
+    /// A long hall and a tall hall, open at both ends, and an arena with doors
+    /// on its top and left edges.
+    fn test_footprints() -> Vec<Footprint> {
+        vec![
+            Footprint::from_segments(IVec2::new(2, 1), |wall, _| {
+                matches!(wall, WALL_UP | WALL_DOWN)
+            })
+            .unwrap(),
+            Footprint::from_segments(IVec2::new(1, 2), |wall, _| {
+                matches!(wall, WALL_LEFT | WALL_RIGHT)
+            })
+            .unwrap(),
+            Footprint::from_segments(IVec2::new(2, 2), |wall, segment| {
+                !matches!((wall, segment), (WALL_UP, 1) | (WALL_LEFT, 0))
+            })
+            .unwrap(),
+        ]
+    }
+
+    #[test]
+    fn rooms_spanning_several_cells_match_their_footprints() {
+        let mut placed_any = false;
+
+        for name in GENERATOR_NAMES {
+            let generator = by_name(name).unwrap();
+
+            for seed in 0..100 {
+                for (bounds, limit) in [
+                    (None, ExploreLimit::Radius(6)),
+                    (parse_bounds("7x5"), ExploreLimit::Rooms(usize::MAX)),
+                ] {
+                    let mut grid = DungeonGrid {
+                        bounds,
+                        footprints: test_footprints(),
+                        ..Default::default()
+                    };
+                    grid.explore(&DungeonSeed(seed), &*generator, limit);
+                    assert_consistent(&grid);
+
+                    for (cell, grid_cell) in grid.iter() {
+                        let anchor = grid_cell.anchor;
+                        let footprint = grid.room_footprint(anchor).unwrap();
+
+                        if footprint.is_single_cell() {
+                            assert_eq!(anchor, *cell);
+                            continue;
+                        }
+
+                        placed_any = true;
+                        assert!(grid.footprints.contains(&footprint), "room at {anchor}");
+                        assert!(footprint
+                            .cells()
+                            .all(|(offset, code)| grid.code(anchor + offset) == Some(code)));
+                        assert_eq!(grid_cell.role, None);
+                    }
+                }
+            }
+        }
+
+        assert!(placed_any);
+    }
+
+    #[test]
+    fn bounded_dungeon_with_rooms_spanning_several_cells_is_connected() {
+        let bounds = parse_bounds("7x5").unwrap();
+        let generator = by_name("random").unwrap();
+
+        for seed in 0..100 {
+            let seed = DungeonSeed(seed);
+            let mut grid = DungeonGrid {
+                bounds: Some(bounds),
+                footprints: test_footprints(),
+                ..Default::default()
+            };
+            grid.explore(&seed, &*generator, ExploreLimit::Rooms(usize::MAX));
+
+            let mut reached = HashSet::from([IVec2::ZERO]);
+            let mut to_visit = vec![IVec2::ZERO];
+
+            while let Some(cell) = to_visit.pop() {
+                let code = grid.code(cell).unwrap();
+
+                for (direction, wall, _) in SIDES {
+                    let neighbour = cell + direction;
+
+                    if code & wall == 0 && reached.insert(neighbour) {
+                        assert!(grid.contains_key(&neighbour), "door into nothing at {cell}");
+                        to_visit.push(neighbour);
+                    }
+                }
+            }
+
+            assert_eq!(reached.len(), grid.len());
+
+            let stairs = grid
+                .keys()
+                .filter(|cell| grid.has_stairs(&seed, **cell))
+                .count();
+            assert_eq!(stairs, 1);
+        }
+    }
+
+    #[test]
+    fn no_special_rooms_without_role_codes() {
+        let generator = by_name("random").unwrap();
+        let mut grid = DungeonGrid::default();
+        grid.explore(&DungeonSeed(7), &*generator, ExploreLimit::Radius(12));
+
+        assert!(grid.values().all(|grid_cell| grid_cell.role.is_none()));
+    }
+}
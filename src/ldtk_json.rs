@@ -0,0 +1,310 @@
+//! Writing levels back into an LDtk project, for the tools which edit it.
+//!
+//! Levels are written the way the LDtk editor itself lays them out, and only
+//! the `levels` array of a single world is replaced, or a new world added, so
+//! the rest of the file is left byte for byte as it was and the changes show
+//! up as small diffs.
+
+use std::ops::Range;
+
+use serde_json::{Map, Value};
+
+/// Arrays whose objects are written one key per line, rather than inline.
+const BLOCK_ARRAYS: [&str; 3] = ["levels", "layerInstances", "entityInstances"];
+
+/// How deeply nested the keys of an entity instance are. Every array from
+/// this depth down is written inline.
+const ENTITY_DEPTH: usize = 7;
+
+/// How deeply nested the levels of a world are.
+const LEVEL_DEPTH: usize = 2;
+
+/// The text of a level, as LDtk writes it in the `levels` array of a world.
+pub fn level_text(level: &Value) -> String {
+    let mut out = "\t".repeat(LEVEL_DEPTH);
+    write_element(&mut out, level, LEVEL_DEPTH);
+    out
+}
+
+/// Replace the levels of the given world in the text of an LDtk project.
+///
+/// Returns `None` if the project has no world with that identifier.
+pub fn replace_world_levels(text: &str, world: &str, levels: &[Value]) -> Option<String> {
+    let range = world_levels_range(text, world)?;
+
+    let levels = if levels.is_empty() {
+        String::new()
+    } else {
+        let levels: Vec<_> = levels.iter().map(level_text).collect();
+        format!("\n{}\n\t", levels.join(",\n"))
+    };
+
+    Some(format!(
+        "{}{levels}{}",
+        &text[..range.start],
+        &text[range.end..]
+    ))
+}
+
+/// Add a world to the end of the `worlds` array in the text of an LDtk
+/// project, written the way LDtk writes its worlds, along with its levels.
+///
+/// Returns `None` if the project has no `worlds` array, or the world isn't an
+/// object with an `identifier`.
+pub fn insert_world(text: &str, world: &Value) -> Option<String> {
+    const WORLDS: &str = "\"worlds\": [";
+
+    let identifier = world["identifier"].as_str()?;
+    let levels = world["levels"].as_array().cloned().unwrap_or_default();
+
+    // The world itself is written inline, and then its levels as a block.
+    let mut header = world.as_object()?.clone();
+    header.insert("levels".into(), Value::Array(Vec::new()));
+
+    let start = text.find(WORLDS)? + WORLDS.len();
+    let end = closing_bracket(text, start)?;
+
+    let mut world_text = String::new();
+    if !text[start..end].trim().is_empty() {
+        world_text.push_str(", ");
+    }
+    write_inline_object(&mut world_text, &header);
+
+    let text = format!("{}{world_text}{}", &text[..end], &text[end..]);
+    replace_world_levels(&text, identifier, &levels)
+}
+
+/// An iid made from the given one by flipping the bits of the mask. Always
+/// the same for the same iid and mask, so tools writing copies of levels
+/// give them the same iids from one run to the next.
+///
+/// Returns `None` if the iid isn't a UUID.
+pub fn masked_iid(iid: &str, mask: u128) -> Option<String> {
+    let iid = u128::from_str_radix(&iid.replace('-', ""), 16).ok()? ^ mask;
+    let hex = format!("{iid:032x}");
+
+    Some(format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    ))
+}
+
+/// The 64-bit FNV-1a hash of a string, which unlike the standard library's
+/// hashers is the same on every platform and Rust version.
+pub fn fnv1a(text: &str) -> u64 {
+    text.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Replace the `nextUid` of an LDtk project, the next free uid for new
+/// levels, layers, and definitions.
+///
+/// Returns `None` if the project has no `nextUid`.
+pub fn replace_next_uid(text: &str, next_uid: i64) -> Option<String> {
+    const KEY: &str = "\"nextUid\": ";
+
+    let start = text.find(KEY)? + KEY.len();
+    let end = start
+        + text[start..]
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(text.len() - start);
+
+    Some(format!("{}{next_uid}{}", &text[..start], &text[end..]))
+}
+
+/// The byte range between the brackets of the `levels` array of the given
+/// world.
+fn world_levels_range(text: &str, world: &str) -> Option<Range<usize>> {
+    const LEVELS: &str = "\"levels\": [";
+
+    let worlds = text.find("\"worlds\":")?;
+    let identifier = format!("\"identifier\": {}", Value::from(world));
+    let world = worlds + text[worlds..].find(&identifier)?;
+    let start = world + text[world..].find(LEVELS)? + LEVELS.len();
+
+    Some(start..closing_bracket(text, start)?)
+}
+
+/// The index of the bracket closing the array or object which starts just
+/// before `start`.
+fn closing_bracket(text: &str, start: usize) -> Option<usize> {
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+
+    for (index, byte) in text.bytes().enumerate().skip(start) {
+        match byte {
+            _ if escaped => escaped = false,
+            b'\\' if in_string => escaped = true,
+            b'"' => in_string = !in_string,
+            _ if in_string => {}
+            b'[' | b'{' => depth += 1,
+            b']' | b'}' if depth == 0 => return Some(index),
+            b']' | b'}' => depth -= 1,
+            _ => {}
+        }
+    }
+
+    None
+}
+
+/// Write an element of one of the [BLOCK_ARRAYS], one key per line.
+fn write_element(out: &mut String, value: &Value, depth: usize) {
+    let Value::Object(object) = value else {
+        write_inline(out, value);
+        return;
+    };
+
+    out.push_str("{\n");
+
+    for (index, (key, value)) in object.iter().enumerate() {
+        indent(out, depth + 1);
+        write_inline(out, &Value::from(key.as_str()));
+        out.push_str(": ");
+        write_value(out, key, value, depth + 1);
+
+        if index + 1 < object.len() {
+            out.push(',');
+        }
+        out.push('\n');
+    }
+
+    indent(out, depth);
+    out.push('}');
+}
+
+/// Write the value of the given key of an element.
+fn write_value(out: &mut String, key: &str, value: &Value, depth: usize) {
+    let items = match value {
+        Value::Array(items) if !items.is_empty() && depth < ENTITY_DEPTH => items,
+        _ => return write_inline(out, value),
+    };
+
+    let block = BLOCK_ARRAYS.contains(&key);
+    if !block && key != "intGridCsv" && !items.iter().all(Value::is_object) {
+        return write_inline(out, value);
+    }
+
+    out.push_str("[\n");
+
+    for (index, item) in items.iter().enumerate() {
+        indent(out, depth + 1);
+
+        if block {
+            write_element(out, item, depth + 1);
+        } else {
+            write_inline(out, item);
+        }
+
+        if index + 1 < items.len() {
+            out.push(',');
+        }
+        out.push('\n');
+    }
+
+    indent(out, depth);
+    out.push(']');
+}
+
+/// Write a value on a single line, with a space inside the braces of
+/// objects.
+fn write_inline(out: &mut String, value: &Value) {
+    match value {
+        Value::Object(object) if object.is_empty() => out.push_str("{}"),
+        Value::Object(object) => write_inline_object(out, object),
+        Value::Array(items) => {
+            out.push('[');
+            for (index, item) in items.iter().enumerate() {
+                if index > 0 {
+                    out.push(',');
+                }
+                write_inline(out, item);
+            }
+            out.push(']');
+        }
+        // Scalars always serialize.
+        _ => out.push_str(&serde_json::to_string(value).expect("scalar should serialize")),
+    }
+}
+
+fn write_inline_object(out: &mut String, object: &Map<String, Value>) {
+    out.push_str("{ ");
+
+    for (index, (key, value)) in object.iter().enumerate() {
+        if index > 0 {
+            out.push_str(", ");
+        }
+        write_inline(out, &Value::from(key.as_str()));
+        out.push_str(": ");
+        write_inline(out, value);
+    }
+
+    out.push_str(" }");
+}
+
+fn indent(out: &mut String, depth: usize) {
+    out.extend(std::iter::repeat_n('\t', depth));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROJECT: &str = include_str!("../assets/ldtk/dungeon_of_madness.ldtk");
+
+    fn dungeon_levels(text: &str) -> Vec<Value> {
+        let project: Value = serde_json::from_str(text).unwrap();
+
+        project["worlds"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|world| world["identifier"] == "Dungeon")
+            .unwrap()["levels"]
+            .as_array()
+            .unwrap()
+            .clone()
+    }
+
+    #[test]
+    fn rewriting_the_levels_leaves_the_project_unchanged() {
+        let levels = dungeon_levels(PROJECT);
+
+        assert_eq!(
+            replace_world_levels(PROJECT, "Dungeon", &levels).as_deref(),
+            Some(PROJECT)
+        );
+    }
+
+    #[test]
+    fn replaced_levels_are_read_back() {
+        let levels = dungeon_levels(PROJECT);
+        let text = replace_world_levels(PROJECT, "Dungeon", &levels[..2]).unwrap();
+        let text = replace_next_uid(&text, 1000).unwrap();
+
+        assert_eq!(dungeon_levels(&text), &levels[..2]);
+        assert!(text.contains("\"nextUid\": 1000,"));
+        assert!(replace_world_levels(PROJECT, "Nowhere", &levels).is_none());
+    }
+
+    #[test]
+    fn inserted_worlds_are_read_back() {
+        let levels = dungeon_levels(PROJECT);
+        let world = serde_json::json!({
+            "iid": "00000000-0000-0000-0000-000000000001",
+            "identifier": "Copy",
+            "worldLayout": "Free",
+            "levels": levels[..2],
+        });
+        let text = insert_world(PROJECT, &world).unwrap();
+
+        let project: Value = serde_json::from_str(&text).unwrap();
+        assert_eq!(project["worlds"][1], world);
+        assert_eq!(dungeon_levels(&text), levels);
+    }
+}
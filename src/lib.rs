@@ -0,0 +1,59 @@
+//! The parts of Dungeon of Madness which don't need a window: the layout
+//! rules, the generators, and the room templates read from the LDtk project.
+//!
+//! These are shared between the game and the `dungeon_layout`,
+//! `render_layout`, `mirror_rooms` and `lint_project` tools.
+
+pub mod doors;
+pub mod export;
+pub mod footprints;
+pub mod generator;
+pub mod layout;
+pub mod ldtk_json;
+pub mod lint;
+pub mod mirror;
+pub mod pins;
+pub mod render;
+pub mod roles;
+pub mod room_templates;
+pub mod themes;
+pub mod walls;
+
+/// Read a launch option: the `--<name> <value>` (or `--<name>=<value>`)
+/// command line argument on native builds.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn launch_option(name: &str) -> Option<String> {
+    let flag = format!("--{name}");
+    let mut args = std::env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        if arg == flag {
+            return args.next();
+        }
+
+        if let Some(value) = arg
+            .strip_prefix(&flag)
+            .and_then(|rest| rest.strip_prefix('='))
+        {
+            return Some(value.to_string());
+        }
+    }
+
+    None
+}
+
+/// Read a launch option: the `?<name>=<value>` URL query parameter in the web
+/// build.
+#[cfg(target_arch = "wasm32")]
+pub fn launch_option(name: &str) -> Option<String> {
+    let search = web_sys::window()
+        .and_then(|window| window.location().search().ok())
+        .unwrap_or_default();
+
+    search
+        .trim_start_matches('?')
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value.to_string())
+}
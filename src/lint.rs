@@ -0,0 +1,298 @@
+//! Checks of the LDtk project for authoring mistakes, which the game would
+//! otherwise only trip over at runtime. Run by the `lint_project` tool.
+//!
+//! Every level of the `Dungeon` world must be usable as a room template, see
+//! [room_templates](crate::room_templates), which means being a whole number
+//! of [LEVEL_SIZE](crate::layout::LEVEL_SIZE) cells across and down, with an
+//! IntGrid covering it. Its doors come from the gaps in its walls, see
+//! [walls](crate::walls), so the door fields it may still have must agree with
+//! them.
+//!
+//! The mirrored copies of levels must still match their originals, see
+//! [mirror](crate::mirror), or the copies drift as the originals are edited.
+
+use bevy::prelude::*;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::footprints::Footprint;
+use crate::mirror::{level_field, Mirror, MIRROR_OF_FIELD};
+use crate::room_templates::{
+    RoomTemplate, RoomTemplateError, RoomTemplates, DOOR_FIELDS, DUNGEON_WORLD,
+};
+
+/// A mistake found in the LDtk project.
+#[derive(Debug, thiserror::Error)]
+pub enum LintProblem {
+    #[error(transparent)]
+    Template(#[from] RoomTemplateError),
+    #[error("level {level} has a door in {field}, but no gap in the walls there")]
+    MissingGap { level: String, field: &'static str },
+    #[error("level {level} has a gap in the walls at {field}, but no door there")]
+    UnexpectedGap { level: String, field: &'static str },
+    #[error(
+        "level {level} no longer matches {original}, which it mirrors. Run: cargo run --bin \
+         mirror_rooms"
+    )]
+    StaleMirror { level: String, original: String },
+    #[error("level {level} mirrors {original}, which doesn't ask to be mirrored that way")]
+    OrphanMirror { level: String, original: String },
+}
+
+/// Check every level in the `Dungeon` world of an LDtk project.
+pub fn lint_project(bytes: &[u8]) -> Result<Vec<LintProblem>, serde_json::Error> {
+    let (room_templates, errors) = RoomTemplates::from_project_json(bytes)?;
+    let project: ProjectJson = serde_json::from_slice(bytes)?;
+    let levels: Vec<Value> = project
+        .worlds
+        .iter()
+        .filter(|world| world.identifier == DUNGEON_WORLD)
+        .flat_map(|world| world.levels.iter().cloned())
+        .collect();
+
+    let mut problems: Vec<LintProblem> = errors.into_iter().map(LintProblem::from).collect();
+
+    for level in &levels {
+        let level = LevelJson::deserialize(level)?;
+
+        // Levels which aren't room templates are already reported.
+        if let Some(room) = room_templates.by_identifier(&level.identifier) {
+            problems.extend(lint_door_fields(&level, room));
+        }
+    }
+
+    problems.extend(lint_mirrors(&levels));
+
+    Ok(problems)
+}
+
+/// Check every mirrored copy against the copy the `mirror_rooms` tool would
+/// make of its original now.
+fn lint_mirrors(levels: &[Value]) -> Vec<LintProblem> {
+    let mut problems = Vec::new();
+
+    for copy in levels {
+        let Some(original) = level_field(copy, MIRROR_OF_FIELD).as_str() else {
+            continue;
+        };
+        let level = copy["identifier"].as_str().unwrap_or_default().to_string();
+        let original_level = levels
+            .iter()
+            .find(|candidate| candidate["identifier"] == original);
+
+        let mirror = Mirror::ALL.into_iter().find(|mirror| {
+            mirror.identifier(original) == level
+                && original_level
+                    .is_some_and(|original| level_field(original, mirror.field()) == true)
+        });
+
+        let (Some(mirror), Some(original_level)) = (mirror, original_level) else {
+            problems.push(LintProblem::OrphanMirror {
+                level,
+                original: original.to_string(),
+            });
+            continue;
+        };
+
+        let uid = copy["uid"].as_i64().unwrap_or_default();
+
+        if mirror.level(original_level, uid).ok().as_ref() != Some(copy) {
+            problems.push(LintProblem::StaleMirror {
+                level,
+                original: original.to_string(),
+            });
+        }
+    }
+
+    problems
+}
+
+/// Check the door fields of a level against the doors of its room template,
+/// which come from its walls.
+fn lint_door_fields(level: &LevelJson, room: &RoomTemplate) -> Vec<LintProblem> {
+    let cells = room
+        .footprint
+        .as_ref()
+        .map_or(IVec2::ONE, |footprint| footprint.size());
+
+    let mut problems = Vec::new();
+
+    for (fields, wall) in DOOR_FIELDS {
+        for segment in 0..Footprint::segments(cells, wall) {
+            let field = fields[segment as usize];
+            let Some(door) = level
+                .field_instances
+                .iter()
+                .find(|instance| instance.identifier == field)
+                .and_then(|instance| instance.value.as_bool())
+            else {
+                continue;
+            };
+
+            let gap = room
+                .cell_code(Footprint::segment_cell(cells, wall, segment))
+                .is_some_and(|code| code & wall == 0);
+            let level = level.identifier.clone();
+
+            match (door, gap) {
+                (true, false) => problems.push(LintProblem::MissingGap { level, field }),
+                (false, true) => problems.push(LintProblem::UnexpectedGap { level, field }),
+                _ => {}
+            }
+        }
+    }
+
+    problems
+}
+
+// Just enough of the LDtk project JSON format to read the door fields. The
+// levels are kept whole, to compare mirrored copies with.
+
+#[derive(Deserialize)]
+struct ProjectJson {
+    worlds: Vec<WorldJson>,
+}
+
+#[derive(Deserialize)]
+struct WorldJson {
+    identifier: String,
+    levels: Vec<Value>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LevelJson {
+    identifier: String,
+    field_instances: Vec<FieldInstanceJson>,
+}
+
+#[derive(Deserialize)]
+struct FieldInstanceJson {
+    #[serde(rename = "__identifier")]
+    identifier: String,
+    #[serde(rename = "__value")]
+    value: Value,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROJECT: &str = include_str!("../assets/ldtk/dungeon_of_madness.ldtk");
+
+    #[test]
+    fn project_has_no_problems() {
+        let problems = lint_project(PROJECT.as_bytes()).unwrap();
+        assert!(problems.is_empty(), "{problems:#?}");
+    }
+
+    fn edit_walls(project: &mut Value, level: &str, edit: impl Fn(&mut [Value])) {
+        let level = project["worlds"][0]["levels"]
+            .as_array_mut()
+            .unwrap()
+            .iter_mut()
+            .find(|candidate| candidate["identifier"] == level)
+            .unwrap();
+        let layer = level["layerInstances"]
+            .as_array_mut()
+            .unwrap()
+            .iter_mut()
+            .find(|layer| layer["__identifier"] == "IntGrid")
+            .unwrap();
+
+        edit(layer["intGridCsv"].as_array_mut().unwrap());
+    }
+
+    #[test]
+    fn door_fields_which_disagree_with_walls_are_found() {
+        let mut project: Value = serde_json::from_str(PROJECT).unwrap();
+
+        // Level_0 has a door on every side: wall up the top one. Level_1 is
+        // closed to the north: knock a hole in it. Opening a corner of Level_0
+        // doesn't matter, as both of the edges it's on have doors. The door
+        // fields are left as they were.
+        edit_walls(&mut project, "Level_0", |csv| {
+            csv[3..6].fill(1.into());
+            csv[9 * 8] = 0.into();
+        });
+        edit_walls(&mut project, "Level_1", |csv| csv[4] = 0.into());
+
+        let problems = lint_project(&serde_json::to_vec(&project).unwrap()).unwrap();
+        let found: Vec<_> = problems
+            .iter()
+            // The mirrored copies of the edited levels are out of date too.
+            .filter(|problem| !matches!(problem, LintProblem::StaleMirror { .. }))
+            .map(|problem| match problem {
+                LintProblem::MissingGap { level, field } => ("missing", level.as_str(), *field),
+                LintProblem::UnexpectedGap { level, field } => {
+                    ("unexpected", level.as_str(), *field)
+                }
+                _ => panic!("unexpected problem: {problem}"),
+            })
+            .collect();
+
+        assert_eq!(
+            found,
+            [
+                ("missing", "Level_0", "DoorUp"),
+                ("unexpected", "Level_1", "DoorUp")
+            ]
+        );
+    }
+
+    #[test]
+    fn levels_of_the_wrong_size_are_found() {
+        let mut project: Value = serde_json::from_str(PROJECT).unwrap();
+        project["worlds"][0]["levels"][0]["pxWid"] = 150.into();
+
+        let problems = lint_project(&serde_json::to_vec(&project).unwrap()).unwrap();
+        assert!(matches!(
+            problems[..],
+            [
+                LintProblem::Template(RoomTemplateError::InvalidSize { .. }),
+                LintProblem::StaleMirror { .. }
+            ]
+        ));
+    }
+
+    #[test]
+    fn mirrored_copies_which_drifted_are_found() {
+        let mut project: Value = serde_json::from_str(PROJECT).unwrap();
+
+        // Edit one original without mirroring it again, and stop another one
+        // from being mirrored.
+        for level in project["worlds"][0]["levels"].as_array_mut().unwrap() {
+            if level["identifier"] == "Level_2" {
+                level["__bgColor"] = "#123456".into();
+            } else if level["identifier"] == "Level_3" {
+                for field in level["fieldInstances"].as_array_mut().unwrap() {
+                    if field["__identifier"] == "FlipX" {
+                        field["__value"] = false.into();
+                    }
+                }
+            }
+        }
+
+        let problems = lint_project(&serde_json::to_vec(&project).unwrap()).unwrap();
+        let found: Vec<_> = problems
+            .iter()
+            .map(|problem| match problem {
+                LintProblem::StaleMirror { level, original } => {
+                    ("stale", level.as_str(), original.as_str())
+                }
+                LintProblem::OrphanMirror { level, original } => {
+                    ("orphan", level.as_str(), original.as_str())
+                }
+                _ => panic!("unexpected problem: {problem}"),
+            })
+            .collect();
+
+        assert_eq!(
+            found,
+            [
+                ("stale", "Level_2_FlipX", "Level_2"),
+                ("orphan", "Level_3_FlipX", "Level_3")
+            ]
+        );
+    }
+}
@@ -1,25 +1,60 @@
+use std::collections::VecDeque;
 use std::f32::consts::FRAC_1_SQRT_2;
 
 use bevy::color::palettes::tailwind::GRAY_500;
 use bevy::input::mouse::MouseWheel;
+use bevy::platform::collections::{HashMap, HashSet};
 use bevy::prelude::*;
 use bevy::render::render_resource::{AsBindGroup, ShaderType};
 use bevy::shader::ShaderRef;
 use bevy::sprite_render::{AlphaMode2d, Material2d, Material2dPlugin};
 use bevy::window::WindowMode;
+use bevy_ldtk_asset::level::Level as LevelAsset;
 use shieldtank::prelude::*;
-use tinyrand::{Rand as _, StdRand};
+
+#[cfg(not(target_arch = "wasm32"))]
+use dungeon_of_madness::export::{self, ExportedRoom};
+use dungeon_of_madness::generator::{self, DungeonGenerator};
+use dungeon_of_madness::launch_option;
+use dungeon_of_madness::layout::{
+    CellLevel, Depth, DungeonGrid, DungeonSeed, ExploreLimit, GridCell, LEVEL_SIZE, SIDES,
+};
+use dungeon_of_madness::pins::{PinnedLayout, PinnedLayoutLoader};
+use dungeon_of_madness::render::STAIRS_TILE;
+use dungeon_of_madness::room_templates::{
+    RoomTemplates, RoomTemplatesLoader, PROJECT_FILE, START_HALL,
+};
+use dungeon_of_madness::themes::{Theme, ThemeMap};
 
 const WINDOW_RESOLUTION: UVec2 = UVec2::new(1280, 960);
 
-const PROJECT_FILE: &str = "ldtk/dungeon_of_madness.ldtk";
 const DUNGEON_IID: u128 = iid!("6b6032f1-e920-11ef-b902-3d698dd98675").as_u128();
 const SKELETON_IID: u128 = iid!("4be48e10-e920-11ef-b902-6dc2806b1269").as_u128();
 const START_HALL_IID: u128 = iid!("29c72090-1030-11f0-8f0e-c7ebf6f05d5f").as_u128();
-const LEVEL_SIZE: f32 = 144.0;
+
+/// How far, in level grid cells, a level may be from the [CurrentLevel] before
+/// it is unloaded.
+const LEVEL_STREAMING_RADIUS: i32 = 4;
+
+/// How many steps through open doors from the [CurrentLevel] rooms are spawned
+/// ahead of the player.
+const LOOKAHEAD_RADIUS: i32 = 2;
+
+/// At most this many levels are spawned per frame, so loading never hitches.
+const LEVEL_SPAWN_BUDGET: usize = 2;
+
+const TILESET_PATH: &str = "tilesets/Dungeon_Tileset_v2.png";
+
+/// The stairs are drawn in the middle of the room, just above the floor.
+const STAIRS_OFFSET: Vec3 = Vec3::new(LEVEL_SIZE / 2.0, -LEVEL_SIZE / 2.0, 0.5);
+/// How close the skeleton has to get to the middle of the stairs to take them.
+const STAIRS_REACH: f32 = 8.0;
 
 const CLOUDS_SHADER_PATH: &str = "shaders/clouds.wesl";
 const CLOUDS_Z: f32 = 900.0;
+/// How quickly the clouds change over to those of a new theme. Higher is
+/// faster.
+const CLOUDS_THEME_BLEND_RATE: f32 = 1.5;
 
 const PLAYER_MOVE_SPEED: f32 = 90.0;
 
@@ -28,34 +63,270 @@ const CAMERA_ZOOM_SPEED: f32 = 3.0;
 const CAMERA_ZOOM_MIN: f32 = 0.1;
 const CAMERA_ZOOM_MAX: f32 = 2.0;
 
-const LEVEL_UP: Vec2 = Vec2::new(0.0, LEVEL_SIZE);
-const LEVEL_RIGHT: Vec2 = Vec2::new(LEVEL_SIZE, 0.0);
-const LEVEL_DOWN: Vec2 = Vec2::new(0.0, -LEVEL_SIZE);
-const LEVEL_LEFT: Vec2 = Vec2::new(-LEVEL_SIZE, 0.0);
+/// The key which exports the current floor into the LDtk project, see
+/// [export_floor].
+#[cfg(not(target_arch = "wasm32"))]
+const EXPORT_KEY: KeyCode = KeyCode::F2;
+
+/// The [DungeonGenerator] which picks the door code for each new room.
+///
+/// Read from the `generator` launch option, see [launch_option]. Defaults to
+/// [RandomGenerator](generator::RandomGenerator).
+#[derive(Resource, Deref)]
+struct SelectedGenerator(Box<dyn DungeonGenerator>);
+
+impl SelectedGenerator {
+    fn from_env() -> Self {
+        if let Some(name) = launch_option("generator") {
+            match generator::by_name(&name) {
+                Some(generator) => return Self(generator),
+                None => warn!(
+                    "Ignoring unknown generator: {name}. Expected one of: {}",
+                    generator::GENERATOR_NAMES.join(", ")
+                ),
+            }
+        }
+
+        Self(Box::new(generator::RandomGenerator))
+    }
+}
+
+/// The location of the level in the given grid cell, which is its upper left
+/// corner.
+fn cell_location(cell: IVec2) -> Vec2 {
+    cell.as_vec2() * LEVEL_SIZE
+}
 
-const WALL_UP: u16 = 0x1;
-const WALL_RIGHT: u16 = 0x2;
-const WALL_DOWN: u16 = 0x4;
-const WALL_LEFT: u16 = 0x8;
+/// The asset path of the level with the given identifier in the `Dungeon`
+/// world.
+fn level_asset_path(identifier: &str) -> String {
+    format!("{PROJECT_FILE}#world:Dungeon/{identifier}")
+}
 
-/// We track the level under the player in a resourse.
+/// The grid cell containing the given world location.
+///
+/// A level's location is its upper left corner, so it covers the area to the
+/// right of and below that point.
+fn location_cell(location: Vec2) -> IVec2 {
+    IVec2::new(
+        (location.x / LEVEL_SIZE).floor() as i32,
+        (location.y / LEVEL_SIZE).ceil() as i32,
+    )
+}
+
+/// We track the grid cell of the level under the player in a resourse.
 ///
 /// Used by [track_current_level]
 #[derive(Resource, Deref, DerefMut)]
-struct CurrentLevel(Entity);
+struct CurrentLevel(IVec2);
+
+/// The [RoomTemplates] read from the LDtk project, which
+/// [attempt_spawn_level] picks new levels from.
+#[derive(Resource, Deref)]
+struct RoomTemplatesHandle(Handle<RoomTemplates>);
+
+/// The [PinnedLayout] named by the `layout` launch option, if any, whose
+/// rooms are pinned to the first floor. Loaded from
+/// `assets/layouts/<name>.layout`.
+#[derive(Resource, Deref)]
+struct PinnedLayoutHandle(Option<Handle<PinnedLayout>>);
+
+/// Handles to the level of every room in the [RoomTemplates], keyed by
+/// template iid.
+///
+/// Every level is loaded by [preload_levels] before play starts, and held here
+/// for the rest of the game, so [attempt_spawn_level] never waits on a load.
+#[derive(Resource, Default)]
+struct PreloadedLevels {
+    handles: HashMap<u128, Handle<LevelAsset>>,
+    /// Whether every level has finished loading, or failed to.
+    ready: bool,
+}
+
+/// The [revision](dungeon_of_madness::room_templates::RoomTemplate::revision)
+/// of every room template, as of the last time the [RoomTemplates] were
+/// loaded, so [track_room_revisions] can tell which rooms were edited when the
+/// LDtk project is reloaded.
+#[derive(Resource, Default, Deref, DerefMut)]
+struct RoomRevisions(HashMap<u128, u64>);
+
+/// How far along reloading the LDtk project is, with the `hot_reload` feature.
+///
+/// The project is read by both the [RoomTemplatesLoader] and shieldtank, which
+/// finish reloading it in either order, so the edited rooms are only respawned
+/// by [respawn_edited_rooms] once both have.
+#[derive(Resource, Default)]
+enum ProjectReload {
+    #[default]
+    Idle,
+    /// shieldtank has reloaded the levels, but not yet the [RoomTemplates].
+    LevelsReloaded,
+    /// The [RoomTemplates] have been reloaded, with these templates edited,
+    /// but shieldtank hasn't reloaded the levels yet.
+    TemplatesReloaded(HashSet<u128>),
+    /// Both are reloaded, and the rooms of these templates can be respawned.
+    Ready(HashSet<u128>),
+}
+
+impl ProjectReload {
+    fn templates_reloaded(&mut self, edited: HashSet<u128>) {
+        *self = match std::mem::take(self) {
+            Self::Idle => Self::TemplatesReloaded(edited),
+            Self::LevelsReloaded => Self::Ready(edited),
+            Self::TemplatesReloaded(mut templates) => {
+                templates.extend(edited);
+                Self::TemplatesReloaded(templates)
+            }
+            Self::Ready(mut templates) => {
+                templates.extend(edited);
+                Self::Ready(templates)
+            }
+        };
+    }
 
-/// When we move to another level, or when we start, we send this event for all
-/// chunks in the directions north, east, south, and west.
+    fn levels_reloaded(&mut self) {
+        *self = match std::mem::take(self) {
+            Self::Idle | Self::LevelsReloaded => Self::LevelsReloaded,
+            Self::TemplatesReloaded(templates) | Self::Ready(templates) => Self::Ready(templates),
+        };
+    }
+}
+
+/// Where the skeleton was when the start hall was respawned, so
+/// [restore_skeleton] can put it back once the new start hall is loaded.
+#[derive(Resource, Deref)]
+struct RestoreSkeleton(Transform);
+
+/// The progress text shown while in [GameState::Loading].
+#[derive(Component)]
+struct LoadingText;
+
+/// Levels further than this many grid cells from the [CurrentLevel] are
+/// despawned by [unload_distant_levels].
+///
+/// Distance is measured per axis, so the loaded area is a square around the
+/// player. Read from the `streaming_radius` launch option, see
+/// [launch_option]. Defaults to [LEVEL_STREAMING_RADIUS].
+#[derive(Resource, Clone, Copy, Debug, Deref, DerefMut)]
+struct LevelStreamingRadius(i32);
+
+impl LevelStreamingRadius {
+    fn from_env() -> Self {
+        Self(numeric_launch_option(
+            "streaming_radius",
+            1,
+            LEVEL_STREAMING_RADIUS,
+        ))
+    }
+}
+
+/// Rooms up to this many steps through open doors from the [CurrentLevel] are
+/// spawned ahead of the player, by [spawn_lookahead_levels].
+///
+/// This should stay below the [LevelStreamingRadius], or levels would be
+/// unloaded as soon as they're spawned. Read from the `lookahead_radius`
+/// launch option, see [launch_option]. Defaults to [LOOKAHEAD_RADIUS].
+#[derive(Resource, Clone, Copy, Debug, Deref, DerefMut)]
+struct LookaheadRadius(i32);
+
+impl LookaheadRadius {
+    fn from_env(streaming_radius: LevelStreamingRadius) -> Self {
+        let lookahead_radius = numeric_launch_option("lookahead_radius", 0, LOOKAHEAD_RADIUS);
+        let max = *streaming_radius - 1;
+
+        if lookahead_radius > max {
+            warn!(
+                "Lowering lookahead_radius {lookahead_radius} to {max}, below the streaming_radius"
+            );
+            return Self(max);
+        }
+
+        Self(lookahead_radius)
+    }
+}
+
+/// How many levels [spawn_lookahead_levels] may spawn in a single frame.
+///
+/// Read from the `spawn_budget` launch option, see [launch_option]. Defaults
+/// to [LEVEL_SPAWN_BUDGET].
+#[derive(Resource, Clone, Copy, Debug, Deref, DerefMut)]
+struct LevelSpawnBudget(usize);
+
+impl LevelSpawnBudget {
+    fn from_env() -> Self {
+        Self(numeric_launch_option("spawn_budget", 1, LEVEL_SPAWN_BUDGET))
+    }
+}
+
+/// Read a number of at least `min` from the given launch option, see
+/// [launch_option], falling back to `default` if it's missing or bad.
+fn numeric_launch_option<T>(name: &str, min: T, default: T) -> T
+where
+    T: std::str::FromStr + PartialOrd + std::fmt::Display,
+{
+    let Some(value) = launch_option(name) else {
+        return default;
+    };
+
+    match value.parse() {
+        Ok(number) if number >= min => number,
+        _ => {
+            warn!("Ignoring bad {name}: {value}. Expected a number of at least {min}");
+            default
+        }
+    }
+}
+
+/// The cells still to visit in the breadth-first walk through open doors from
+/// the [CurrentLevel], along with how many steps away they are.
+///
+/// Restarted whenever the player enters another level.
+#[derive(Resource, Default)]
+struct SpawnQueue {
+    queue: VecDeque<(IVec2, i32)>,
+    /// Every cell which has been queued since the walk was restarted.
+    queued: HashSet<IVec2>,
+    /// The cells a level has been asked for, which are waiting for the
+    /// [AttemptSpawnLevel] observer to run.
+    requested: HashSet<IVec2>,
+}
+
+impl SpawnQueue {
+    /// Start a new walk from the given cell.
+    fn restart(&mut self, cell: IVec2) {
+        self.queue.clear();
+        self.queued.clear();
+        self.requested.clear();
+
+        self.queue.push_back((cell, 0));
+        self.queued.insert(cell);
+    }
+}
+
+/// The stairs down to the next floor, spawned in the rooms which
+/// [DungeonGrid::has_stairs]. See [take_stairs].
+#[derive(Component)]
+struct Stairs;
+
+/// Attached to every level spawned by [attempt_spawn_level], recording which
+/// [DungeonGrid] cell it belongs to. For a room spanning several cells, this
+/// is its top left cell.
+#[derive(Component, Clone, Copy, Debug, Deref)]
+struct LevelCell(IVec2);
+
+/// Sent by [spawn_lookahead_levels] for each grid cell it reaches which has no
+/// level spawned.
 ///
 /// This is handled by the [attempt_spawn_level] observer
 #[derive(Event, Deref)]
-struct AttemptSpawnLevel(Vec2);
+struct AttemptSpawnLevel(IVec2);
 
 /// The current state. This is a very simple state. We stay in
 /// [GameState::Loading] until we find the start hall has been loaded.
-/// Once loaded, [wait_for_start_hall] will send [AttemptSpawnLevel] messages
-/// in the four cardinal directions and transition to [GameState::Playing],
-/// where we remain until the player closes the window.
+/// Once loaded, [wait_for_start_hall] will start spawning the levels around it
+/// and transition to [GameState::Playing],
+/// where we remain until the player takes the [Stairs] down. Then it's back to
+/// [GameState::Loading] while the next floor's start hall loads.
 #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, States)]
 enum GameState {
     #[default]
@@ -123,6 +394,23 @@ impl Default for CloudsMaterial {
     }
 }
 
+impl CloudsMaterial {
+    /// The default clouds, with whatever the given theme overrides.
+    fn themed(theme: &Theme) -> Self {
+        let mut material = Self::default();
+
+        if let Some(clouds) = theme.clouds {
+            material.params0.speed = clouds.speed;
+            material.params0.scale = clouds.scale;
+            material.params1.color_gain = clouds.color_gain;
+            material.params1.color_offset = clouds.color_offset;
+            material.params1.alpha = clouds.alpha;
+        }
+
+        material
+    }
+}
+
 /// Implement [CloudsMaterial] as a [Material2d] with Bevy.
 impl Material2d for CloudsMaterial {
     fn fragment_shader() -> ShaderRef {
@@ -138,41 +426,26 @@ impl Material2d for CloudsMaterial {
 #[derive(Component)]
 struct Clouds;
 
-/// Convenience function for getting the `code` for the given level name.
-///
-/// Expected format:
-/// - If the level name is `"Start_Hall"`, then return zero.
-/// - Otherwise, we expect the level name to be in the form of `"Level_n"` where
-///   _n_ is between 0 to 15.
-fn parse_level_code(level_name: &str) -> u16 {
-    match level_name {
-        "Start_Hall" => 0,
-        name if &name[0..6] == "Level_" => {
-            let code = level_name[6..]
-                .parse()
-                .expect("Couldn't parse code: {rest}");
-            if (0..15).contains(&code) {
-                code
-            } else {
-                panic!("Code out of range! {code}")
-            }
-        }
-
-        bad_name => panic!("bad level name! {bad_name}"),
-    }
-}
-
 /// Spawn all the initial components:
 /// - Camera
 /// - Start Hall
 /// - Text Label
 /// - Clouds mesh
 fn setup(
+    seed: Res<DungeonSeed>,
+    generator: Res<SelectedGenerator>,
+    grid: Res<DungeonGrid>,
     asset_server: Res<AssetServer>,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<CloudsMaterial>>,
     mut commands: Commands,
 ) {
+    info!("Dungeon seed: {}, generator: {}", **seed, generator.name());
+
+    if let Some(bounds) = grid.bounds {
+        info!("Dungeon bounds: {} to {}", bounds.min, bounds.max);
+    }
+
     // The camera, initialized to the default scale. It's actually not where we
     // want, but when the skeleton spawns later on then it'll get moved.
     commands.spawn((
@@ -180,6 +453,17 @@ fn setup(
         Transform::default().with_scale(Vec2::splat(CAMERA_ZOOM_DEFAULT).extend(1.0)),
     ));
 
+    // The door codes of every room we can spawn, from the project's levels.
+    // Asked for by type, as the project's own loader is shieldtank's, see
+    // [RoomTemplatesLoader].
+    commands.insert_resource(RoomTemplatesHandle(
+        asset_server.load::<RoomTemplates>(PROJECT_FILE),
+    ));
+
+    commands.insert_resource(PinnedLayoutHandle(
+        launch_option("layout").map(|name| asset_server.load(format!("layouts/{name}.layout"))),
+    ));
+
     // The start hall, which also contains the player skeleton in the
     // `Entities` layer.
     //
@@ -192,7 +476,7 @@ fn setup(
         ChildAutoloadFilter::None,
         children![(
             LdtkLevel {
-                handle: asset_server.load(format!("{PROJECT_FILE}#world:Dungeon/Start_Hall")),
+                handle: asset_server.load(level_asset_path(START_HALL)),
                 ..Default::default()
             },
             Transform::default(),
@@ -202,7 +486,11 @@ fn setup(
     // A text banner at the bottom describing the player keybinds.
     commands.spawn((
         Name::new("Keys description"),
-        Text::new("Movement: WASD or Arrow Keys\nZoom in/out: Mouse Scroll"),
+        Text::new(format!(
+            "Movement: WASD or Arrow Keys\nZoom in/out: Mouse Scroll\nSeed: {} ({})",
+            **seed,
+            generator.name()
+        )),
         TextFont {
             font: asset_server.load("fonts/IMMORTAL.ttf"),
             font_size: 22.0,
@@ -254,6 +542,31 @@ fn cloud_material_update_parallax(
     material.params2.parallax = skeleton_translation.truncate();
 }
 
+/// Blend the clouds towards those of the theme of the [CurrentLevel], so each
+/// part of the dungeon has its own sky.
+fn cloud_material_update_theme(
+    time: Res<Time>,
+    seed: Res<DungeonSeed>,
+    depth: Res<Depth>,
+    theme_map: Res<ThemeMap>,
+    current_level: Res<CurrentLevel>,
+    material: Single<&MeshMaterial2d<CloudsMaterial>>,
+    mut materials: ResMut<Assets<CloudsMaterial>>,
+) {
+    let material = materials.get_mut(*material).unwrap();
+    let target = CloudsMaterial::themed(theme_map.theme(&seed.floor(**depth), **current_level));
+
+    let t = 1.0 - (-CLOUDS_THEME_BLEND_RATE * time.delta_secs()).exp();
+    let blend = |from: f32, to: f32| from + (to - from) * t;
+
+    material.params0.speed = blend(material.params0.speed, target.params0.speed);
+    material.params0.scale = material.params0.scale.lerp(target.params0.scale, t);
+    material.params1.color_gain = blend(material.params1.color_gain, target.params1.color_gain);
+    material.params1.color_offset =
+        blend(material.params1.color_offset, target.params1.color_offset);
+    material.params1.alpha = blend(material.params1.alpha, target.params1.alpha);
+}
+
 /// Move the camera and clouds mesh to always be centered over the player
 /// skeleton.
 ///
@@ -297,25 +610,169 @@ fn camera_mouse_wheel_zoom(
     }
 }
 
-/// This system will only run once. When the start hall is loaded and the
-/// [ShieldtankWorldBounds] component is added:
+/// Show the [LoadingText] while in [GameState::Loading].
+fn spawn_loading_text(asset_server: Res<AssetServer>, mut commands: Commands) {
+    commands.spawn((
+        Name::new("Loading text"),
+        LoadingText,
+        Text::new("Loading rooms..."),
+        TextFont {
+            font: asset_server.load("fonts/IMMORTAL.ttf"),
+            font_size: 32.0,
+            ..Default::default()
+        },
+        TextColor(GRAY_500.into()),
+        TextLayout::new_with_justify(Justify::Center),
+        Node {
+            position_type: PositionType::Absolute,
+            top: Val::Px(40.0),
+            left: Val::Px(5.0),
+            right: Val::Px(5.0),
+            ..default()
+        },
+    ));
+}
+
+fn despawn_loading_text(
+    loading_text_query: Query<Entity, With<LoadingText>>,
+    mut commands: Commands,
+) {
+    for loading_text in loading_text_query.iter() {
+        commands.entity(loading_text).despawn();
+    }
+}
+
+/// Once the [RoomTemplates] are loaded, start loading the level of every room
+/// template, and keep the [LoadingText] up to date until they're all done.
+///
+/// Only runs the loads once. On later floors the levels are already held in
+/// [PreloadedLevels], so it's ready straight away.
+fn preload_levels(
+    room_templates: Res<Assets<RoomTemplates>>,
+    room_templates_handle: Res<RoomTemplatesHandle>,
+    asset_server: Res<AssetServer>,
+    mut preloaded: ResMut<PreloadedLevels>,
+    mut loading_text_query: Query<&mut Text, With<LoadingText>>,
+) {
+    if preloaded.ready {
+        return;
+    }
+
+    let Some(room_templates) = room_templates.get(&**room_templates_handle) else {
+        return;
+    };
+
+    if preloaded.handles.is_empty() {
+        for room_template in &room_templates.rooms {
+            preloaded.handles.insert(
+                room_template.iid,
+                asset_server.load(level_asset_path(&room_template.identifier)),
+            );
+        }
+    }
+
+    let total = preloaded.handles.len();
+    let loaded = preloaded
+        .handles
+        .values()
+        .filter(|handle| asset_server.is_loaded_with_dependencies(handle.id()))
+        .count();
+    let failed = preloaded
+        .handles
+        .values()
+        .filter(|handle| asset_server.load_state(handle.id()).is_failed())
+        .count();
+
+    for mut loading_text in loading_text_query.iter_mut() {
+        **loading_text = format!("Loading rooms: {loaded}/{total}");
+    }
+
+    if loaded + failed == total {
+        if failed > 0 {
+            error!("{failed} of {total} room levels failed to load!");
+        }
+
+        info!("Loaded {loaded} room levels");
+        preloaded.ready = true;
+    }
+}
+
+/// This system will only run once per floor. When the start hall is loaded and
+/// the [ShieldtankWorldBounds] component is added, and the [RoomTemplates] and
+/// [PreloadedLevels] are loaded, along with the [PinnedLayout] if there is
+/// one:
+/// - Place the start hall, and the pinned rooms if this is the first floor
+/// - Lay out the whole floor, if the dungeon is bounded
 /// - Create the [CurrentLevel] resource
-/// - Send four [AttemptSpawnLevel] in each direction
+/// - Restart the [SpawnQueue] from the start hall
 /// - Change state to [GameState::Playing].
 ///
-/// [attempt_spawn_level] will spawn levels in each direction, since there won't
-/// be any there already.
+/// [spawn_lookahead_levels] then walks the [SpawnQueue] out through the start
+/// hall's doors, and has [attempt_spawn_level] spawn the rooms behind them.
 fn wait_for_start_hall(
     level_query: SingleByIid<START_HALL_IID, Entity, With<LdtkWorldBounds>>,
+    seed: Res<DungeonSeed>,
+    depth: Res<Depth>,
+    generator: Res<SelectedGenerator>,
+    room_templates: Res<Assets<RoomTemplates>>,
+    room_templates_handle: Res<RoomTemplatesHandle>,
+    pinned_layouts: Res<Assets<PinnedLayout>>,
+    pinned_layout_handle: Res<PinnedLayoutHandle>,
+    asset_server: Res<AssetServer>,
+    mut grid: ResMut<DungeonGrid>,
+    preloaded: Res<PreloadedLevels>,
+    mut spawn_queue: ResMut<SpawnQueue>,
     mut next_state: ResMut<NextState<GameState>>,
     mut commands: Commands,
 ) {
-    commands.insert_resource(CurrentLevel(*level_query));
+    let Some(room_templates) = room_templates.get(&**room_templates_handle) else {
+        return;
+    };
+
+    if !preloaded.ready {
+        return;
+    }
+
+    // If the pinned layout failed to load, the error is already logged, and
+    // the whole floor is left to the generator.
+    let pinned_layout = match &**pinned_layout_handle {
+        Some(handle) => match pinned_layouts.get(handle) {
+            Some(pinned_layout) => Some(pinned_layout),
+            None if asset_server.load_state(handle.id()).is_failed() => None,
+            None => return,
+        },
+        None => None,
+    };
+
+    // Special rooms can only be given the roles, and doors, we have rooms for.
+    // Likewise for rooms spanning several cells.
+    grid.role_codes = room_templates.role_codes();
+    grid.footprints = room_templates.footprints();
 
-    commands.trigger(AttemptSpawnLevel(LEVEL_UP));
-    commands.trigger(AttemptSpawnLevel(LEVEL_RIGHT));
-    commands.trigger(AttemptSpawnLevel(LEVEL_DOWN));
-    commands.trigger(AttemptSpawnLevel(LEVEL_LEFT));
+    grid.place_start_hall(Some(START_HALL_IID), CellLevel::Loaded(*level_query));
+
+    // Pinned rooms go in first, so the rest of the floor is laid out around
+    // them.
+    if let Some(pinned_layout) = pinned_layout.filter(|_| **depth == 0) {
+        for error in pinned_layout.apply(&mut grid, room_templates) {
+            error!("Skipping pinned room: {error}");
+        }
+    }
+
+    // A bounded dungeon is laid out in full up front, so every room in it can
+    // be reached. Levels are then spawned from the layout as the player walks
+    // in.
+    if grid.bounds.is_some() {
+        grid.explore(
+            &seed.floor(**depth),
+            &*generator.0,
+            ExploreLimit::Rooms(usize::MAX),
+        );
+    }
+
+    commands.insert_resource(CurrentLevel(IVec2::ZERO));
+
+    spawn_queue.restart(IVec2::ZERO);
 
     next_state.set(GameState::Playing);
 }
@@ -323,133 +780,730 @@ fn wait_for_start_hall(
 /// Watch the skeleton location, and update the [CurrentLevel] resource if
 /// changed.
 ///
-/// Also, if the level changed, we send a [AttemptSpawnLevel] signal in each
-/// of the four surrouding directions. [attempt_spawn_level] will spawn a level
-/// there if the space is open.
+/// Also, if the level changed, we restart the [SpawnQueue] from the new level,
+/// so [spawn_lookahead_levels] spawns the rooms around it.
 fn track_current_level(
     skeleton_location: SingleByIid<
         SKELETON_IID,
         &GlobalTransform,
         // ShieldtankLocationChanged
     >,
-    level_query: QueryByWorldBounds<(Entity, &Name, ShieldtankWorldLocation), With<LdtkLevel>>,
+    grid: Res<DungeonGrid>,
+    level_query: Query<&Name, With<LdtkLevel>>,
     mut current_level: ResMut<CurrentLevel>,
-    mut commands: Commands,
+    mut spawn_queue: ResMut<SpawnQueue>,
 ) {
-    let skeleton_location = skeleton_location.translation().truncate();
+    let skeleton_cell = location_cell(skeleton_location.translation().truncate());
 
-    let Ok((level_under_skeleton, level_name, level_location)) =
-        level_query.single_by_location(skeleton_location)
-    else {
+    if !grid.is_loaded(skeleton_cell) {
         info!("Skeleton is walking in space!");
         return;
-    };
+    }
 
-    if level_under_skeleton != **current_level {
-        info!("Skeleton has wandered into a new level! {level_name}");
-        **current_level = level_under_skeleton;
+    if skeleton_cell != **current_level {
+        // Walking between the cells of a room spanning several cells doesn't
+        // count as a new level.
+        if let Some(level_name) = grid
+            .level(skeleton_cell)
+            .filter(|level| grid.level(**current_level) != Some(*level))
+            .and_then(|level| level_query.get(level).ok())
+        {
+            info!("Skeleton has wandered into a new level! {level_name}");
+        }
 
-        let level_location = level_location.get();
+        **current_level = skeleton_cell;
 
-        let level_code = parse_level_code(level_name);
+        spawn_queue.restart(skeleton_cell);
+    }
+}
 
-        if level_code & WALL_UP == 0 {
-            commands.trigger(AttemptSpawnLevel(level_location + LEVEL_UP));
+/// Walk breadth first through open doors from the [CurrentLevel], sending an
+/// [AttemptSpawnLevel] for every cell within the [LookaheadRadius] which has
+/// no level yet.
+///
+/// A new room's doors aren't known until [attempt_spawn_level] has run, so a
+/// requested cell is looked at again in the next frame, and the walk carries
+/// on through it from there. Only the [LevelSpawnBudget] levels are requested
+/// per frame, the rest wait their turn.
+fn spawn_lookahead_levels(
+    grid: Res<DungeonGrid>,
+    lookahead_radius: Res<LookaheadRadius>,
+    spawn_budget: Res<LevelSpawnBudget>,
+    mut spawn_queue: ResMut<SpawnQueue>,
+    mut commands: Commands,
+) {
+    let SpawnQueue {
+        queue,
+        queued,
+        requested,
+    } = &mut *spawn_queue;
+
+    let mut spawned = 0;
+    let mut waiting = Vec::new();
+
+    while let Some((cell, steps)) = queue.pop_front() {
+        if grid.level(cell).is_none() {
+            // Asked for last frame, but nothing was spawned, so there's no
+            // room to be had here.
+            if requested.contains(&cell) {
+                continue;
+            }
+
+            if spawned == **spawn_budget {
+                queue.push_front((cell, steps));
+                break;
+            }
+
+            commands.trigger(AttemptSpawnLevel(cell));
+            requested.insert(cell);
+            spawned += 1;
+
+            waiting.push((cell, steps));
+            continue;
         }
 
-        if level_code & WALL_RIGHT == 0 {
-            commands.trigger(AttemptSpawnLevel(level_location + LEVEL_RIGHT));
+        let Some(code) = grid.code(cell) else {
+            continue;
+        };
+
+        if steps >= **lookahead_radius {
+            continue;
         }
 
-        if level_code & WALL_DOWN == 0 {
-            commands.trigger(AttemptSpawnLevel(level_location + LEVEL_DOWN));
+        for (direction, wall, _) in SIDES {
+            let neighbour = cell + direction;
+
+            if code & wall == 0 && queued.insert(neighbour) {
+                queue.push_back((neighbour, steps + 1));
+            }
         }
+    }
 
-        if level_code & WALL_LEFT == 0 {
-            commands.trigger(AttemptSpawnLevel(level_location + LEVEL_LEFT));
+    // Back to the front, so the walk picks up where it left off.
+    for (cell, steps) in waiting.into_iter().rev() {
+        queue.push_front((cell, steps));
+    }
+}
+
+/// Despawn every level outside of the [LevelStreamingRadius] around the
+/// [CurrentLevel].
+///
+/// The cells stay in the [DungeonGrid], so the same rooms are spawned again
+/// when the player comes back. The start hall is never unloaded, since the
+/// skeleton lives in its entities layer.
+///
+/// A room spanning several cells is measured from its top left cell, so all of
+/// its cells are unloaded together.
+fn unload_distant_levels(
+    current_level: Res<CurrentLevel>,
+    streaming_radius: Res<LevelStreamingRadius>,
+    start_hall: SingleByIid<START_HALL_IID, Entity>,
+    mut grid: ResMut<DungeonGrid>,
+    mut commands: Commands,
+) {
+    let mut unloaded = HashSet::new();
+
+    for grid_cell in grid.values_mut() {
+        let Some(level) = grid_cell.level.entity() else {
+            continue;
+        };
+
+        if level == *start_hall {
+            continue;
+        }
+
+        let distance = (grid_cell.anchor - **current_level).abs().max_element();
+
+        if distance > **streaming_radius {
+            if unloaded.insert(level) {
+                info!("Unloading distant level at: {}", grid_cell.anchor);
+                commands.entity(level).despawn();
+            }
+
+            grid_cell.level = CellLevel::Unloaded;
         }
     }
 }
 
 /// The observer which responds to the [AttemptSpawnLevel] event.
+///
+/// A room spanning several cells is spawned as a single level, in its top left
+/// cell, whichever of its cells was asked for.
 fn attempt_spawn_level(
-    attemt_level_location: On<AttemptSpawnLevel>,
+    attempt_level_cell: On<AttemptSpawnLevel>,
     dungeon: SingleByIid<DUNGEON_IID, Entity>,
-    level_query: QueryByWorldBounds<&Name, With<LdtkLevel>>,
+    seed: Res<DungeonSeed>,
+    depth: Res<Depth>,
+    generator: Res<SelectedGenerator>,
+    theme_map: Res<ThemeMap>,
+    room_templates: Res<Assets<RoomTemplates>>,
+    room_templates_handle: Res<RoomTemplatesHandle>,
     asset_server: Res<AssetServer>,
-    mut rand: Local<StdRand>,
+    mut grid: ResMut<DungeonGrid>,
     mut commands: Commands,
 ) {
-    // Coerce the event into the global `Vec2`.
-    let attempt_level_location: Vec2 = **attemt_level_location;
-    info!("Spawning new level at: {attempt_level_location}");
-
-    // QueryByGlobalBounds is inclusive for all bounds, so we sample from the
-    // center instead of attempt_level_location, which is the upper left corner.
-    const CENTER_OFFSET: Vec2 = Vec2::new(LEVEL_SIZE / 2.0, -LEVEL_SIZE / 2.0);
-
-    // If a level already exists here, we return early.
-    if level_query
-        .single_by_location(attempt_level_location + CENTER_OFFSET)
-        .is_ok()
-    {
+    // Coerce the event into the grid cell.
+    let cell: IVec2 = **attempt_level_cell;
+
+    // Every floor is laid out from its own seed.
+    let seed = seed.floor(**depth);
+
+    // If a level already exists here, or is still loading, or the cell is off
+    // the edge of a bounded dungeon, we return early.
+    if grid.level(cell).is_some() || !grid.in_bounds(cell) {
         return;
     }
 
-    // `Some(code)` if there's a level to the north, `None` if there's no
-    // level there (yet).
-    let level_up_code = level_query
-        .single_by_location(attempt_level_location + CENTER_OFFSET + LEVEL_UP)
-        .ok()
-        .map(|level_name| parse_level_code(level_name));
-
-    // Similar, but to the east.
-    let level_right_code = level_query
-        .single_by_location(attempt_level_location + CENTER_OFFSET + LEVEL_RIGHT)
-        .ok()
-        .map(|level_name| parse_level_code(level_name));
-
-    // Similar, but to the south.
-    let level_down_code = level_query
-        .single_by_location(attempt_level_location + CENTER_OFFSET + LEVEL_DOWN)
-        .ok()
-        .map(|level_name| parse_level_code(level_name));
-
-    // Similar, but to the west.
-    let level_left_code = level_query
-        .single_by_location(attempt_level_location + CENTER_OFFSET + LEVEL_LEFT)
-        .ok()
-        .map(|level_name| parse_level_code(level_name));
-
-    // Generate a new number from 0 to 15
-    let mut rand = rand.next_lim_u16(15);
-
-    let mut fix_rand_by_code = |code: Option<u16>, wall: u16, opposite_wall: u16| {
-        if let Some(code) = code {
-            if code & opposite_wall == 0 {
-                rand &= wall ^ 0xF;
-            } else {
-                rand |= wall;
+    let Some(room_templates) = room_templates.get(&**room_templates_handle) else {
+        return;
+    };
+
+    // If we've been here before, or the dungeon was laid out up front, bring
+    // back the same room. Otherwise lay out a new one, the same way the
+    // `dungeon_layout` tool does.
+    if !grid.contains_key(&cell) {
+        match grid
+            .choose_footprint(&seed, cell)
+            .map(|(anchor, footprint)| (anchor, footprint.clone()))
+        {
+            Some((anchor, footprint)) => {
+                grid.place_room(anchor, &footprint, None, CellLevel::Unloaded)
+            }
+            None => {
+                let code = grid.choose_code(&seed, &*generator.0, cell);
+                let role = grid.choose_role(&seed, cell, code);
+                grid.place(
+                    cell,
+                    GridCell {
+                        code,
+                        anchor: cell,
+                        template: None,
+                        role,
+                        level: CellLevel::Unloaded,
+                    },
+                );
             }
         }
+    }
+
+    let GridCell {
+        code,
+        anchor,
+        template,
+        role,
+        ..
+    } = grid[&cell];
+
+    info!("Spawning new level at: {anchor}");
+
+    // Bring back the same room if we've been here before. Otherwise pick one of
+    // the rooms with matching doors and role, from the theme of this part of
+    // the dungeon.
+    let theme = theme_map.theme(&seed, anchor);
+    let room_template = match template {
+        Some(iid) => room_templates.by_iid(iid),
+        None => room_templates.pick(
+            &seed,
+            anchor,
+            code,
+            role,
+            grid.room_footprint(anchor).as_ref(),
+            theme.name,
+        ),
+    };
+
+    let Some(room_template) = room_template else {
+        error!("No room template with door code {code} and role {role:?} at {anchor}!");
+        return;
     };
 
-    // If a level already exists in the given direction, explicitly set the
-    // respective bit, so the doors match.
-    fix_rand_by_code(level_up_code, WALL_UP, WALL_DOWN);
-    fix_rand_by_code(level_right_code, WALL_RIGHT, WALL_LEFT);
-    fix_rand_by_code(level_down_code, WALL_DOWN, WALL_UP);
-    fix_rand_by_code(level_left_code, WALL_LEFT, WALL_RIGHT);
+    // Spawn the new level, using the bevy_ldtk_asset asset path. It's already
+    // loaded, see [PreloadedLevels].
+    let new_level_asset_label = level_asset_path(&room_template.identifier);
+    let level = commands
+        .spawn((
+            LdtkLevel {
+                handle: asset_server.load(new_level_asset_label),
+                ..Default::default()
+            },
+            Transform::default().with_translation(cell_location(anchor).extend(0.0)),
+            LevelCell(anchor),
+            ChildOf(*dungeon),
+        ))
+        .id();
+
+    if let Some(role) = role {
+        info!("Placing a {role} room at: {anchor}");
+    }
+
+    let room_cells: Vec<IVec2> = grid.room_cells(anchor).collect();
+
+    for room_cell in &room_cells {
+        if let Some(grid_cell) = grid.get_mut(room_cell) {
+            grid_cell.template = Some(room_template.iid);
+            grid_cell.level = CellLevel::Pending(level);
+        }
+    }
+
+    // The stairs are a child of the level, so they're unloaded along with it.
+    for room_cell in room_cells {
+        if grid.has_stairs(&seed, room_cell) {
+            let offset = cell_location(room_cell) - cell_location(anchor);
+
+            commands.spawn((
+                Name::new("Stairs"),
+                Stairs,
+                Sprite {
+                    image: asset_server.load(TILESET_PATH),
+                    rect: Some(STAIRS_TILE),
+                    ..Default::default()
+                },
+                Transform::from_translation(STAIRS_OFFSET + offset.extend(0.0)),
+                ChildOf(level),
+            ));
+        }
+    }
+}
+
+/// When the skeleton steps onto the [Stairs], go down to the next floor.
+///
+/// Every level of the current floor is despawned, taking the skeleton with the
+/// start hall, and a fresh start hall is spawned. We go back to
+/// [GameState::Loading] until it's loaded, and [wait_for_start_hall] starts
+/// the new floor from there, laid out from the next [DungeonSeed::floor].
+fn take_stairs(
+    skeleton_location: SingleByIid<SKELETON_IID, &GlobalTransform>,
+    stairs_query: Query<&GlobalTransform, With<Stairs>>,
+    dungeon: SingleByIid<DUNGEON_IID, Entity>,
+    asset_server: Res<AssetServer>,
+    mut depth: ResMut<Depth>,
+    mut grid: ResMut<DungeonGrid>,
+    mut next_state: ResMut<NextState<GameState>>,
+    mut commands: Commands,
+) {
+    let skeleton = skeleton_location.translation().truncate();
+
+    if !stairs_query
+        .iter()
+        .any(|stairs| stairs.translation().truncate().distance(skeleton) < STAIRS_REACH)
+    {
+        return;
+    }
+
+    **depth += 1;
+    info!("Skeleton takes the stairs down to depth {}", **depth);
+
+    // Rooms spanning several cells share a level between their cells.
+    let levels: HashSet<Entity> = grid
+        .values()
+        .filter_map(|grid_cell| grid_cell.level.entity())
+        .collect();
+
+    for level in levels {
+        commands.entity(level).despawn();
+    }
+
+    *grid = grid.next_floor();
 
-    // Spawn the new level, using the bevy_ldtk_asset asset path.
-    let new_level_asset_label = format!("{PROJECT_FILE}#world:Dungeon/Level_{rand}");
-    commands.entity(*dungeon).with_child((
+    commands.spawn((
         LdtkLevel {
-            handle: asset_server.load(new_level_asset_label),
+            handle: asset_server.load(level_asset_path(START_HALL)),
             ..Default::default()
         },
-        Transform::default().with_translation(attempt_level_location.extend(0.0)),
+        Transform::default(),
+        ChildOf(*dungeon),
     ));
+
+    next_state.set(GameState::Loading);
+}
+
+/// The observer which marks the pending [DungeonGrid] cells of a room as
+/// loaded, once its level asset is loaded and [LdtkWorldBounds] is added.
+fn mark_level_loaded(
+    added: On<Add, LdtkWorldBounds>,
+    level_query: Query<&LevelCell, With<LdtkLevel>>,
+    mut grid: ResMut<DungeonGrid>,
+) {
+    let Ok(anchor) = level_query.get(added.entity) else {
+        return;
+    };
+
+    let room_cells: Vec<IVec2> = grid.room_cells(**anchor).collect();
+
+    for room_cell in room_cells {
+        let Some(grid_cell) = grid.get_mut(&room_cell) else {
+            continue;
+        };
+
+        // The level may have been unloaded, and even replaced, before it
+        // finished loading.
+        if grid_cell.level == CellLevel::Pending(added.entity) {
+            grid_cell.level = CellLevel::Loaded(added.entity);
+        }
+    }
+}
+
+/// Whenever the [RoomTemplates] are loaded, note the revision of every room
+/// template. When they're reloaded, with the `hot_reload` feature, find the
+/// templates which were edited or removed, and pass them on to
+/// [ProjectReload].
+///
+/// Rooms which aren't spawned pick up the changes when they're next spawned.
+/// New rooms are laid out with the doors and footprints of the reloaded
+/// templates, but the rooms already laid out keep theirs, so the dungeon
+/// layout stays the same. Rooms whose template no longer has their doors are
+/// given another one which does.
+fn track_room_revisions(
+    mut room_template_events: MessageReader<AssetEvent<RoomTemplates>>,
+    room_templates: Res<Assets<RoomTemplates>>,
+    room_templates_handle: Res<RoomTemplatesHandle>,
+    asset_server: Res<AssetServer>,
+    mut revisions: ResMut<RoomRevisions>,
+    mut preloaded: ResMut<PreloadedLevels>,
+    mut grid: ResMut<DungeonGrid>,
+    mut project_reload: ResMut<ProjectReload>,
+) {
+    let handle = &**room_templates_handle;
+
+    let mut reloaded = false;
+    let mut loaded = false;
+    for event in room_template_events.read() {
+        reloaded |= event.is_modified(handle);
+        loaded |= event.is_added(handle);
+    }
+
+    if !reloaded && !loaded {
+        return;
+    }
+
+    let Some(room_templates) = room_templates.get(handle) else {
+        return;
+    };
+
+    let edited: HashSet<u128> = revisions
+        .iter()
+        .filter(|(iid, revision)| {
+            room_templates
+                .by_iid(**iid)
+                .is_none_or(|room_template| room_template.revision != **revision)
+        })
+        .map(|(iid, _)| *iid)
+        .collect();
+
+    **revisions = room_templates
+        .rooms
+        .iter()
+        .map(|room_template| (room_template.iid, room_template.revision))
+        .collect();
+
+    grid.role_codes = room_templates.role_codes();
+    grid.footprints = room_templates.footprints();
+
+    // Levels added to the project are held along with the rest.
+    if preloaded.ready {
+        for room_template in &room_templates.rooms {
+            preloaded
+                .handles
+                .entry(room_template.iid)
+                .or_insert_with(|| asset_server.load(level_asset_path(&room_template.identifier)));
+        }
+    }
+
+    // Rooms whose template is gone, or whose template's doors no longer match
+    // the room's, are given another one with the same doors and role. The
+    // start hall is never picked, so it keeps its own.
+    let repicked: HashSet<IVec2> = grid
+        .iter()
+        .filter(|(cell, grid_cell)| {
+            grid_cell.template.is_some_and(|iid| {
+                iid != START_HALL_IID
+                    && edited.contains(&iid)
+                    && room_templates.by_iid(iid).is_none_or(|room_template| {
+                        room_template.cell_code(**cell - grid_cell.anchor) != Some(grid_cell.code)
+                    })
+            })
+        })
+        .map(|(_, grid_cell)| grid_cell.anchor)
+        .collect();
+
+    for grid_cell in grid.values_mut() {
+        if repicked.contains(&grid_cell.anchor) {
+            grid_cell.template = None;
+        }
+    }
+
+    if !edited.is_empty() {
+        info!("Room templates edited: {}", edited.len());
+    }
+
+    if reloaded {
+        project_reload.templates_reloaded(edited);
+    }
+}
+
+/// Note when shieldtank has reloaded the levels of the LDtk project, with the
+/// `hot_reload` feature, for [ProjectReload]. Only the levels of the project,
+/// held in [PreloadedLevels], are looked at.
+fn track_level_reloads(
+    mut level_events: MessageReader<AssetEvent<LevelAsset>>,
+    preloaded: Res<PreloadedLevels>,
+    mut project_reload: ResMut<ProjectReload>,
+) {
+    if level_events.read().any(|event| {
+        preloaded
+            .handles
+            .values()
+            .any(|handle| event.is_modified(handle))
+    }) {
+        project_reload.levels_reloaded();
+    }
+}
+
+/// Once the LDtk project is reloaded, see [ProjectReload], despawn every level
+/// spawned from an edited room template, and spawn it again in place.
+///
+/// The skeleton lives in the start hall, so if that is respawned, its
+/// [Transform] is kept in [RestoreSkeleton]. The camera stays put until the
+/// skeleton is back.
+fn respawn_edited_rooms(
+    mut project_reload: ResMut<ProjectReload>,
+    skeleton_transform: Option<SingleByIid<SKELETON_IID, &Transform, With<LdtkEntity>>>,
+    start_hall: SingleByIid<START_HALL_IID, Entity>,
+    mut grid: ResMut<DungeonGrid>,
+    mut commands: Commands,
+) {
+    let ProjectReload::Ready(edited) = &*project_reload else {
+        return;
+    };
+
+    let mut respawned = HashSet::new();
+
+    for grid_cell in grid.values_mut() {
+        let Some(level) = grid_cell.level.entity() else {
+            continue;
+        };
+
+        // Rooms whose template was removed, or no longer fits, have none now,
+        // and are given another one.
+        if grid_cell.template.is_some_and(|iid| !edited.contains(&iid)) {
+            continue;
+        }
+
+        if respawned.insert(grid_cell.anchor) {
+            info!("Respawning edited level at: {}", grid_cell.anchor);
+            commands.entity(level).despawn();
+
+            if level == *start_hall {
+                if let Some(skeleton_transform) = &skeleton_transform {
+                    commands.insert_resource(RestoreSkeleton(**skeleton_transform));
+                }
+            }
+        }
+
+        grid_cell.level = CellLevel::Unloaded;
+    }
+
+    for anchor in respawned {
+        commands.trigger(AttemptSpawnLevel(anchor));
+    }
+
+    *project_reload = ProjectReload::Idle;
+}
+
+/// Once the start hall has been respawned by [respawn_edited_rooms], move the
+/// new skeleton to where the old one was.
+fn restore_skeleton(
+    restore: Res<RestoreSkeleton>,
+    mut skeleton_transform: SingleByIid<SKELETON_IID, &mut Transform, With<LdtkEntity>>,
+    mut commands: Commands,
+) {
+    **skeleton_transform = **restore;
+
+    commands.remove_resource::<RestoreSkeleton>();
+}
+
+/// When the [EXPORT_KEY] is pressed, write the current floor into a new world
+/// of the LDtk project, named after the seed and depth, so designers can open
+/// it in the editor. Pressing it again on the same floor replaces that world.
+///
+/// Every room spawned on this floor so far is exported, along with any pinned
+/// rooms, even if it's since been unloaded. The project is written in place,
+/// on native builds only.
+#[cfg(not(target_arch = "wasm32"))]
+fn export_floor(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    seed: Res<DungeonSeed>,
+    depth: Res<Depth>,
+    grid: Res<DungeonGrid>,
+) {
+    if !keyboard_input.just_pressed(EXPORT_KEY) {
+        return;
+    }
+
+    let rooms: Vec<ExportedRoom> = grid
+        .iter()
+        .filter(|(cell, grid_cell)| **cell == grid_cell.anchor)
+        .filter_map(|(_, grid_cell)| {
+            Some(ExportedRoom {
+                template: grid_cell.template?,
+                anchor: grid_cell.anchor,
+            })
+        })
+        .collect();
+
+    let world = export::world_identifier(seed.0, **depth);
+    let path = dungeon_of_madness::room_templates::project_path();
+
+    let result = std::fs::read_to_string(&path)
+        .map_err(|error| error.to_string())
+        .and_then(|text| {
+            export::export_world(&text, &world, &rooms).map_err(|error| error.to_string())
+        })
+        .and_then(|text| std::fs::write(&path, text).map_err(|error| error.to_string()));
+
+    match result {
+        Ok(()) => info!("Exported {} rooms to world {world} in {path}", rooms.len()),
+        Err(error) => error!("Could not export the floor to {path}: {error}"),
+    }
+}
+
+/// A wall which two loaded levels disagree on: one of them has a door in it,
+/// and the other doesn't. Found by [check_level_doors], and drawn by
+/// [draw_door_mismatches] until either level is unloaded.
+#[cfg(debug_assertions)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+struct DoorMismatch {
+    cell: IVec2,
+    neighbour: IVec2,
+}
+
+#[cfg(debug_assertions)]
+impl DoorMismatch {
+    /// The mismatch in the wall between the two cells, the same whichever
+    /// side it's seen from.
+    fn new(cell: IVec2, neighbour: IVec2) -> Self {
+        let (cell, neighbour) = if cell.to_array() < neighbour.to_array() {
+            (cell, neighbour)
+        } else {
+            (neighbour, cell)
+        };
+
+        Self { cell, neighbour }
+    }
+}
+
+/// Every [DoorMismatch] found so far, once per wall.
+#[cfg(debug_assertions)]
+#[derive(Resource, Default, Deref, DerefMut)]
+struct DoorMismatches(HashSet<DoorMismatch>);
+
+/// In debug builds, the observer which checks the doors of each level as it
+/// finishes loading, against the loaded levels around it and the
+/// [DungeonGrid].
+///
+/// The doors of a level are those of the room template it was spawned from,
+/// so a room opening onto its neighbour's wall is caught as soon as it's
+/// spawned, whatever the grid says.
+#[cfg(debug_assertions)]
+fn check_level_doors(
+    added: On<Add, LdtkWorldBounds>,
+    level_query: Query<&LevelCell, With<LdtkLevel>>,
+    room_templates: Res<Assets<RoomTemplates>>,
+    room_templates_handle: Res<RoomTemplatesHandle>,
+    grid: Res<DungeonGrid>,
+    mut mismatches: ResMut<DoorMismatches>,
+) {
+    let Ok(anchor) = level_query.get(added.entity) else {
+        return;
+    };
+
+    let Some(room_templates) = room_templates.get(&**room_templates_handle) else {
+        return;
+    };
+
+    // The door code of a cell according to its level, along with the name of
+    // the level.
+    let level_code = |cell: IVec2| {
+        let grid_cell = grid.get(&cell)?;
+        let room_template = room_templates.by_iid(grid_cell.template?)?;
+        let code = room_template.cell_code(cell - grid_cell.anchor)?;
+        Some((code, room_template.identifier.as_str()))
+    };
+
+    // A level spawned again in the same place is checked afresh.
+    let room_cells: Vec<IVec2> = grid.room_cells(**anchor).collect();
+    mismatches.retain(|mismatch| {
+        !room_cells.contains(&mismatch.cell) && !room_cells.contains(&mismatch.neighbour)
+    });
+
+    for cell in room_cells {
+        let Some((code, name)) = level_code(cell) else {
+            continue;
+        };
+
+        if grid[&cell].code != code {
+            error!(
+                "Level {name} at {cell} has door code {code}, but the dungeon grid has {}",
+                grid[&cell].code
+            );
+        }
+
+        for (direction, wall, opposite_wall) in SIDES {
+            let neighbour = cell + direction;
+
+            // Walls inside the room, and walls facing levels which aren't
+            // loaded, are left alone.
+            if grid
+                .get(&neighbour)
+                .is_some_and(|grid_cell| grid_cell.anchor == **anchor)
+                || !grid.is_loaded(neighbour)
+            {
+                continue;
+            }
+
+            let Some((neighbour_code, neighbour_name)) = level_code(neighbour) else {
+                continue;
+            };
+
+            if (code & wall != 0) != (neighbour_code & opposite_wall != 0) {
+                error!(
+                    "Door mismatch between level {name} at {cell} and level {neighbour_name} at \
+                     {neighbour}: only one of them has a door in the wall between them"
+                );
+                mismatches.insert(DoorMismatch::new(cell, neighbour));
+            }
+        }
+    }
+}
+
+/// In debug builds, outline both cells of every [DoorMismatch], and draw the
+/// wall between them.
+#[cfg(debug_assertions)]
+fn draw_door_mismatches(
+    grid: Res<DungeonGrid>,
+    mut mismatches: ResMut<DoorMismatches>,
+    mut gizmos: Gizmos,
+) {
+    use bevy::color::palettes::tailwind::{ORANGE_500, RED_500};
+
+    mismatches
+        .retain(|mismatch| grid.is_loaded(mismatch.cell) && grid.is_loaded(mismatch.neighbour));
+
+    let centre = |cell: IVec2| cell_location(cell) + Vec2::new(LEVEL_SIZE, -LEVEL_SIZE) / 2.0;
+
+    for mismatch in mismatches.iter() {
+        for cell in [mismatch.cell, mismatch.neighbour] {
+            gizmos.rect_2d(
+                Isometry2d::from_translation(centre(cell)),
+                Vec2::splat(LEVEL_SIZE - 4.0),
+                ORANGE_500,
+            );
+        }
+
+        let middle = (centre(mismatch.cell) + centre(mismatch.neighbour)) / 2.0;
+        let along = (mismatch.neighbour - mismatch.cell).as_vec2().perp() * LEVEL_SIZE / 2.0;
+        gizmos.line_2d(middle - along, middle + along, RED_500);
+    }
 }
 
 fn player_keyboard_commands(
@@ -576,17 +1630,50 @@ fn main() {
         use bevy_inspector_egui::quick::WorldInspectorPlugin;
         app.add_plugins(EguiPlugin::default())
             .add_plugins(WorldInspectorPlugin::default());
+
+        // Catch rooms whose doors don't line up as soon as they load.
+        app.init_resource::<DoorMismatches>()
+            .add_observer(check_level_doors)
+            .add_systems(
+                Update,
+                draw_door_mismatches.run_if(in_state(GameState::Playing)),
+            );
     }
 
     app.register_asset_reflect::<CloudsMaterial>();
 
+    app.init_asset::<RoomTemplates>()
+        .init_asset_loader::<RoomTemplatesLoader>();
+    app.init_asset::<PinnedLayout>()
+        .init_asset_loader::<PinnedLayoutLoader>();
+
+    // Read after the plugins are added, so that logging is available.
+    app.insert_resource(DungeonSeed::from_env());
+    app.insert_resource(SelectedGenerator::from_env());
+    app.insert_resource(ThemeMap::from_env());
+    let streaming_radius = LevelStreamingRadius::from_env();
+    app.insert_resource(streaming_radius);
+    app.insert_resource(LookaheadRadius::from_env(streaming_radius));
+    app.insert_resource(LevelSpawnBudget::from_env());
+    app.init_resource::<SpawnQueue>();
+    app.init_resource::<PreloadedLevels>();
+    app.init_resource::<RoomRevisions>();
+    app.init_resource::<ProjectReload>();
+    app.init_resource::<Depth>();
+    app.insert_resource(DungeonGrid::from_env());
+
     app.init_state::<GameState>();
 
     app.add_systems(Startup, setup);
 
+    app.add_systems(OnEnter(GameState::Loading), spawn_loading_text);
+    app.add_systems(OnExit(GameState::Loading), despawn_loading_text);
+
     app.add_systems(
         Update,
-        wait_for_start_hall.run_if(in_state(GameState::Loading)),
+        (preload_levels, wait_for_start_hall)
+            .chain()
+            .run_if(in_state(GameState::Loading)),
     );
 
     app.add_systems(
@@ -594,15 +1681,39 @@ fn main() {
         (
             cloud_material_update_time,
             cloud_material_update_parallax,
+            cloud_material_update_theme,
             camera_and_clouds_follow_skeleton,
             camera_mouse_wheel_zoom,
             track_current_level,
+            spawn_lookahead_levels.after(track_current_level),
+            unload_distant_levels
+                .after(track_current_level)
+                .run_if(resource_changed::<CurrentLevel>),
             player_keyboard_commands,
+            // After the distant levels are despawned, so no level is despawned
+            // twice.
+            take_stairs.after(unload_distant_levels),
         )
             .run_if(in_state(GameState::Playing)),
     );
 
+    #[cfg(not(target_arch = "wasm32"))]
+    app.add_systems(Update, export_floor.run_if(in_state(GameState::Playing)));
+
+    // Edits to the LDtk project are picked up with the `hot_reload` feature.
+    app.add_systems(
+        Update,
+        (
+            track_room_revisions,
+            track_level_reloads,
+            respawn_edited_rooms.run_if(in_state(GameState::Playing)),
+            restore_skeleton.run_if(resource_exists::<RestoreSkeleton>),
+        )
+            .chain(),
+    );
+
     app.add_observer(attempt_spawn_level);
+    app.add_observer(mark_level_loaded);
 
     app.run();
 }
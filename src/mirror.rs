@@ -0,0 +1,398 @@
+//! Mirrored copies of the room templates, for more variety from the same
+//! hand-authored levels.
+//!
+//! A level with its `FlipX` level field set, as it is by default, gets a copy
+//! mirrored left to right. One with `FlipY` set gets a copy mirrored top to
+//! bottom as well. `FlipY` is off by default, since the walls of the tileset
+//! are drawn in perspective and look wrong upside down.
+//!
+//! The copies are written into the LDtk project by the `mirror_rooms` tool,
+//! with their `MirrorOf` level field naming the level they were made from, so
+//! they're loaded like any other level. Every tile, the IntGrid collision, the
+//! entities, and the door fields are mirrored. Don't edit the copies by hand:
+//! change the original and run the tool again. The `lint_project` tool fails
+//! on copies which are out of date.
+//!
+//! Rooms are never rotated, since LDtk tiles can only be flipped.
+
+use bevy::prelude::*;
+use serde_json::{json, Value};
+
+use crate::footprints::Footprint;
+use crate::layout::{LEVEL_SIZE, WALL_DOWN, WALL_LEFT, WALL_RIGHT, WALL_UP};
+use crate::ldtk_json::masked_iid;
+use crate::room_templates::DOOR_FIELDS;
+
+/// The level field naming the level a mirrored copy was made from.
+pub const MIRROR_OF_FIELD: &str = "MirrorOf";
+
+/// A way of mirroring a room.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mirror {
+    /// Left to right.
+    Horizontal,
+    /// Top to bottom.
+    Vertical,
+}
+
+impl Mirror {
+    pub const ALL: [Mirror; 2] = [Mirror::Horizontal, Mirror::Vertical];
+
+    /// The level field saying whether a level gets a copy mirrored this way.
+    pub fn field(self) -> &'static str {
+        match self {
+            Mirror::Horizontal => "FlipX",
+            Mirror::Vertical => "FlipY",
+        }
+    }
+
+    /// The wall of the mirrored room which the given wall ends up as.
+    pub fn wall(self, wall: u16) -> u16 {
+        match (self, wall) {
+            (Mirror::Horizontal, WALL_LEFT) => WALL_RIGHT,
+            (Mirror::Horizontal, WALL_RIGHT) => WALL_LEFT,
+            (Mirror::Vertical, WALL_UP) => WALL_DOWN,
+            (Mirror::Vertical, WALL_DOWN) => WALL_UP,
+            _ => wall,
+        }
+    }
+
+    /// The door code of the mirrored room.
+    pub fn code(self, code: u16) -> u16 {
+        [WALL_UP, WALL_RIGHT, WALL_DOWN, WALL_LEFT]
+            .into_iter()
+            .filter(|wall| code & wall != 0)
+            .fold(0, |mirrored, wall| mirrored | self.wall(wall))
+    }
+
+    /// The identifier of the copy of the given level mirrored this way.
+    pub fn identifier(self, identifier: &str) -> String {
+        format!("{identifier}_{}", self.field())
+    }
+
+    /// The segment of the mirrored room's wall which the given segment of a
+    /// wall ends up as. Segments along the walls which stay put are reversed.
+    fn segment(self, size: IVec2, wall: u16, segment: i32) -> i32 {
+        if self.wall(wall) == wall {
+            Footprint::segments(size, wall) - 1 - segment
+        } else {
+            segment
+        }
+    }
+
+    /// Flip a position along the mirrored axis, in a span of the given
+    /// length.
+    fn flip(self, position: IVec2, span: IVec2) -> IVec2 {
+        match self {
+            Mirror::Horizontal => IVec2::new(span.x - position.x, position.y),
+            Mirror::Vertical => IVec2::new(position.x, span.y - position.y),
+        }
+    }
+
+    /// The tile flip bit for this axis, as used by the `f` value of LDtk
+    /// tiles.
+    fn tile_flag(self) -> i64 {
+        match self {
+            Mirror::Horizontal => 1,
+            Mirror::Vertical => 2,
+        }
+    }
+
+    /// The iid of the mirrored copy of something with the given iid. Always
+    /// the same for the same iid, so running the tool again doesn't change
+    /// the copies.
+    fn iid(self, iid: &str) -> Option<String> {
+        let mask: u128 = match self {
+            Mirror::Horizontal => 0xF11F_0000_0001,
+            Mirror::Vertical => 0xF11F_0000_0002,
+        };
+
+        masked_iid(iid, mask)
+    }
+
+    /// A copy of the given LDtk level, mirrored this way, with the given uid.
+    ///
+    /// The copy isn't mirrored again, and its `MirrorOf` field names the
+    /// original level.
+    pub fn level(self, level: &Value, uid: i64) -> Result<Value, MirrorError> {
+        let identifier = level["identifier"].as_str().unwrap_or_default();
+        let malformed = |what: &'static str| MirrorError::Malformed {
+            level: identifier.to_string(),
+            what,
+        };
+
+        let size = IVec2::new(
+            level["pxWid"].as_i64().ok_or_else(|| malformed("pxWid"))? as i32,
+            level["pxHei"].as_i64().ok_or_else(|| malformed("pxHei"))? as i32,
+        );
+        let cells = size / LEVEL_SIZE as i32;
+
+        let mut mirrored = level.clone();
+        mirrored["identifier"] = self.identifier(identifier).into();
+        mirrored["iid"] = self.mirrored_iid(level).ok_or_else(|| malformed("iid"))?;
+        mirrored["uid"] = uid.into();
+        mirrored["useAutoIdentifier"] = false.into();
+        mirrored["__neighbours"] = json!([]);
+
+        // Each door field takes the value of the field it's mirrored from, so
+        // they still agree with the mirrored walls.
+        let fields = mirrored["fieldInstances"]
+            .as_array_mut()
+            .ok_or_else(|| malformed("fieldInstances"))?;
+        let original = fields.clone();
+
+        for (identifiers, wall) in DOOR_FIELDS {
+            let (mirrored_identifiers, _) = DOOR_FIELDS
+                .into_iter()
+                .find(|(_, mirrored_wall)| *mirrored_wall == self.wall(wall))
+                .expect("every wall has door fields");
+
+            for segment in 0..Footprint::segments(cells, wall).min(identifiers.len() as i32) {
+                let from = identifiers[segment as usize];
+                let to = mirrored_identifiers[self.segment(cells, wall, segment) as usize];
+
+                // The door fields are optional, the doors come from the walls.
+                let Some(from) = original.iter().find(|field| field["__identifier"] == from) else {
+                    continue;
+                };
+                let to = fields
+                    .iter_mut()
+                    .find(|field| field["__identifier"] == to)
+                    .ok_or_else(|| malformed("door field"))?;
+
+                to["__value"] = from["__value"].clone();
+                to["realEditorValues"] = from["realEditorValues"].clone();
+            }
+        }
+
+        for mirror in Mirror::ALL {
+            set_field(fields, mirror.field(), false.into(), "V_Bool")
+                .ok_or_else(|| malformed(mirror.field()))?;
+        }
+        set_field(fields, MIRROR_OF_FIELD, identifier.into(), "V_String")
+            .ok_or_else(|| malformed(MIRROR_OF_FIELD))?;
+
+        for layer in mirrored["layerInstances"]
+            .as_array_mut()
+            .ok_or_else(|| malformed("layerInstances"))?
+        {
+            self.mirror_layer(layer, size, uid)
+                .ok_or_else(|| malformed("layer"))?;
+        }
+
+        Ok(mirrored)
+    }
+
+    fn mirrored_iid(self, value: &Value) -> Option<Value> {
+        self.iid(value["iid"].as_str()?).map(Value::from)
+    }
+
+    fn mirror_layer(self, layer: &mut Value, size: IVec2, uid: i64) -> Option<()> {
+        let grid_size = layer["__gridSize"].as_i64()? as i32;
+        let columns = layer["__cWid"].as_i64()? as i32;
+        let rows = layer["__cHei"].as_i64()? as i32;
+
+        layer["iid"] = self.mirrored_iid(layer)?;
+        layer["levelId"] = uid.into();
+
+        let csv = layer["intGridCsv"].as_array()?;
+        if !csv.is_empty() {
+            let mut flipped = csv.clone();
+
+            for (index, value) in csv.iter().enumerate() {
+                let cell = IVec2::new(index as i32 % columns, index as i32 / columns);
+                let cell = self.flip(cell, IVec2::new(columns, rows) - 1);
+                flipped[(cell.y * columns + cell.x) as usize] = value.clone();
+            }
+
+            layer["intGridCsv"] = flipped.into();
+        }
+
+        for tiles in ["autoLayerTiles", "gridTiles"] {
+            for tile in layer[tiles].as_array_mut()? {
+                let px = self.flip(json_ivec2(&tile["px"])?, size - grid_size);
+                let coord_id = px.x / grid_size + px.y / grid_size * columns;
+
+                tile["px"] = json!([px.x, px.y]);
+                tile["f"] = (tile["f"].as_i64()? ^ self.tile_flag()).into();
+                *tile["d"].as_array_mut()?.last_mut()? = coord_id.into();
+            }
+        }
+
+        for entity in layer["entityInstances"].as_array_mut()? {
+            let entity_size = IVec2::new(
+                entity["width"].as_i64()? as i32,
+                entity["height"].as_i64()? as i32,
+            );
+            let pivot = Vec2::new(
+                entity["__pivot"][0].as_f64()? as f32,
+                entity["__pivot"][1].as_f64()? as f32,
+            );
+
+            // Keep the entity over the same tiles, wherever its pivot is.
+            let offset = (entity_size.as_vec2() * (1.0 - 2.0 * pivot)).as_ivec2();
+            let px = self.flip(json_ivec2(&entity["px"])?, size - offset);
+            let grid = px.div_euclid(IVec2::splat(grid_size));
+
+            entity["iid"] = self.mirrored_iid(entity)?;
+            entity["px"] = json!([px.x, px.y]);
+            entity["__grid"] = json!([grid.x, grid.y]);
+        }
+
+        Some(())
+    }
+}
+
+/// The value of the given level field, or `Null` if the level doesn't have it.
+pub fn level_field<'a>(level: &'a Value, identifier: &str) -> &'a Value {
+    level["fieldInstances"]
+        .as_array()
+        .and_then(|fields| {
+            fields
+                .iter()
+                .find(|field| field["__identifier"] == identifier)
+        })
+        .map_or(&Value::Null, |field| &field["__value"])
+}
+
+/// Set a level field, as though it were set in the editor.
+fn set_field(fields: &mut [Value], identifier: &str, value: Value, editor_id: &str) -> Option<()> {
+    let field = fields
+        .iter_mut()
+        .find(|field| field["__identifier"] == identifier)?;
+
+    field["realEditorValues"] = json!([{ "id": editor_id, "params": [value] }]);
+    field["__value"] = value;
+
+    Some(())
+}
+
+fn json_ivec2(value: &Value) -> Option<IVec2> {
+    Some(IVec2::new(
+        value[0].as_i64()? as i32,
+        value[1].as_i64()? as i32,
+    ))
+}
+
+/// Reasons a level can't be mirrored.
+#[derive(Debug, thiserror::Error)]
+pub enum MirrorError {
+    #[error("level {level} can't be mirrored: malformed or missing {what}")]
+    Malformed { level: String, what: &'static str },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::room_templates::RoomTemplates;
+
+    const PROJECT: &str = include_str!("../assets/ldtk/dungeon_of_madness.ldtk");
+
+    /// The levels in the project, along with their room templates.
+    fn project() -> (Value, RoomTemplates) {
+        let project: Value = serde_json::from_str(PROJECT).unwrap();
+        let (templates, errors) = RoomTemplates::from_project_json(PROJECT.as_bytes()).unwrap();
+        assert!(errors.is_empty(), "{errors:?}");
+
+        (project, templates)
+    }
+
+    /// The room templates of the given levels, in a project with the layers of
+    /// the real one.
+    fn templates_of(project: &Value, levels: Vec<Value>) -> RoomTemplates {
+        let project = json!({
+            "defs": project["defs"],
+            "worlds": [{ "identifier": "Dungeon", "levels": levels }],
+        });
+        let bytes = serde_json::to_vec(&project).unwrap();
+        let (templates, errors) = RoomTemplates::from_project_json(&bytes).unwrap();
+        assert!(errors.is_empty(), "{errors:?}");
+
+        templates
+    }
+
+    #[test]
+    fn mirrored_codes_swap_opposite_walls() {
+        assert_eq!(
+            Mirror::Horizontal.code(WALL_UP | WALL_LEFT),
+            WALL_UP | WALL_RIGHT
+        );
+        assert_eq!(
+            Mirror::Vertical.code(WALL_UP | WALL_LEFT),
+            WALL_DOWN | WALL_LEFT
+        );
+
+        for mirror in Mirror::ALL {
+            for code in 0..16 {
+                assert_eq!(mirror.code(mirror.code(code)), code);
+            }
+        }
+    }
+
+    #[test]
+    fn mirrored_levels_have_mirrored_doors() {
+        let (project, templates) = project();
+        let levels = project["worlds"][0]["levels"].as_array().unwrap();
+
+        for mirror in Mirror::ALL {
+            let mirrored: Vec<_> = levels
+                .iter()
+                .filter(|level| level["fieldInstances"].is_array())
+                .enumerate()
+                .map(|(index, level)| mirror.level(level, 1000 + index as i64).unwrap())
+                .collect();
+
+            for (room, mirrored_room) in templates
+                .rooms
+                .iter()
+                .zip(&templates_of(&project, mirrored).rooms)
+            {
+                assert_eq!(
+                    mirrored_room.identifier,
+                    mirror.identifier(&room.identifier)
+                );
+                assert_ne!(mirrored_room.iid, room.iid);
+
+                // A room spanning several cells is mirrored cell by cell.
+                let Some(footprint) = &room.footprint else {
+                    assert_eq!(mirrored_room.code, mirror.code(room.code));
+                    continue;
+                };
+
+                let size = footprint.size();
+                let mut cells: Vec<_> = footprint
+                    .cells()
+                    .map(|(offset, code)| {
+                        let offset = mirror.flip(IVec2::new(offset.x, -offset.y), size - 1);
+                        (IVec2::new(offset.x, -offset.y), mirror.code(code))
+                    })
+                    .collect();
+                cells.sort_by_key(|(offset, _)| (-offset.y, offset.x));
+
+                let mirrored_footprint = mirrored_room.footprint.as_ref().unwrap();
+                assert_eq!(mirrored_footprint.cells().collect::<Vec<_>>(), cells);
+            }
+        }
+    }
+
+    #[test]
+    fn mirroring_twice_gives_back_the_tiles_and_collision() {
+        let (project, _) = project();
+        let level = &project["worlds"][0]["levels"][1];
+
+        for mirror in Mirror::ALL {
+            let twice = mirror
+                .level(
+                    &mirror.level(level, 1).unwrap(),
+                    level["uid"].as_i64().unwrap(),
+                )
+                .unwrap();
+
+            assert_eq!(twice["layerInstances"], level["layerInstances"]);
+            assert_ne!(
+                mirror.level(level, 1).unwrap()["layerInstances"],
+                level["layerInstances"]
+            );
+        }
+    }
+}
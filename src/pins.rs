@@ -0,0 +1,371 @@
+//! Rooms pinned to fixed cells of the first floor, such as a tutorial path
+//! leading out of the start hall.
+//!
+//! A [PinnedLayout] is read from a `.layout` file: an ASCII map of the cells
+//! around the start hall, with a legend naming the room pinned by each letter.
+//! The generator fills in the rest of the floor around the pinned rooms, with
+//! doors which match theirs.
+//!
+//! ```text
+//! # Comment lines start with a hash.
+//! l = Long_Hall
+//! t = Treasure_Room_FlipX
+//!
+//! ....
+//! Sllt
+//! ....
+//! ```
+//!
+//! North is up. `S` is the start hall, and `.` leaves a cell to the
+//! generator. A room spanning several cells has its letter in every cell it
+//! covers. A letter may only be used for a single room.
+
+use bevy::asset::io::Reader;
+use bevy::asset::{AssetLoader, LoadContext};
+use bevy::prelude::*;
+
+use crate::footprints::Footprint;
+use crate::layout::{CellLevel, DungeonGrid, GridCell};
+use crate::room_templates::RoomTemplates;
+
+/// The cell of the map holding the start hall.
+const START_HALL: char = 'S';
+
+/// A cell of the map left to the generator.
+const FREE_CELL: char = '.';
+
+/// A room pinned to fixed cells by a [PinnedLayout].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Pin {
+    /// The letter marking the room in the map.
+    pub letter: char,
+    /// The identifier of the LDtk level to place.
+    pub identifier: String,
+    /// The top left cell of the room.
+    pub anchor: IVec2,
+    /// Every cell marked with the room's letter, top row first.
+    pub cells: Vec<IVec2>,
+}
+
+/// Rooms pinned to fixed cells of the first floor, read from a `.layout`
+/// file.
+#[derive(Asset, TypePath, Clone, Debug, Default)]
+pub struct PinnedLayout {
+    pub pins: Vec<Pin>,
+}
+
+impl PinnedLayout {
+    /// Read a layout from the text of a `.layout` file.
+    pub fn parse(text: &str) -> Result<Self, PinnedLayoutError> {
+        let mut legend = Vec::new();
+        let mut rows = Vec::new();
+
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim_end();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if let Some((letter, identifier)) = line.split_once('=') {
+                let mut letters = letter.trim().chars();
+                let identifier = identifier.trim();
+
+                match (letters.next(), letters.next()) {
+                    (Some(letter), None)
+                        if letter != START_HALL
+                            && letter != FREE_CELL
+                            && !identifier.is_empty() =>
+                    {
+                        legend.push((letter, identifier.to_string()));
+                    }
+                    _ => return Err(PinnedLayoutError::BadLegend { line: index + 1 }),
+                }
+            } else {
+                rows.push(line);
+            }
+        }
+
+        // Map rows run top to bottom, so y goes down until it's flipped below.
+        let marked: Vec<(char, IVec2)> = rows
+            .iter()
+            .enumerate()
+            .flat_map(|(y, row)| {
+                row.chars()
+                    .enumerate()
+                    .map(move |(x, letter)| (letter, IVec2::new(x as i32, y as i32)))
+            })
+            .filter(|(letter, _)| *letter != FREE_CELL)
+            .collect();
+
+        let start_hall = match marked
+            .iter()
+            .filter(|(letter, _)| *letter == START_HALL)
+            .collect::<Vec<_>>()[..]
+        {
+            [(_, start_hall)] => *start_hall,
+            [] => return Err(PinnedLayoutError::NoStartHall),
+            _ => return Err(PinnedLayoutError::SeveralStartHalls),
+        };
+
+        let mut pins: Vec<Pin> = Vec::new();
+
+        for (letter, position) in marked {
+            if letter == START_HALL {
+                continue;
+            }
+
+            let cell = (position - start_hall) * IVec2::new(1, -1);
+
+            if let Some(pin) = pins.iter_mut().find(|pin| pin.letter == letter) {
+                pin.cells.push(cell);
+                pin.anchor = IVec2::new(pin.anchor.x.min(cell.x), pin.anchor.y);
+                continue;
+            }
+
+            let identifier = legend
+                .iter()
+                .find(|(legend_letter, _)| *legend_letter == letter)
+                .map(|(_, identifier)| identifier.clone())
+                .ok_or(PinnedLayoutError::UnknownLetter { letter })?;
+
+            pins.push(Pin {
+                letter,
+                identifier,
+                anchor: cell,
+                cells: vec![cell],
+            });
+        }
+
+        Ok(Self { pins })
+    }
+
+    /// Place every pinned room in the grid, which should hold no more than
+    /// the start hall.
+    ///
+    /// Rooms which can't be placed are left out, and returned so they can be
+    /// reported.
+    pub fn apply(
+        &self,
+        grid: &mut DungeonGrid,
+        room_templates: &RoomTemplates,
+    ) -> Vec<PinnedLayoutError> {
+        self.pins
+            .iter()
+            .filter_map(|pin| pin.apply(grid, room_templates).err())
+            .collect()
+    }
+}
+
+impl Pin {
+    fn apply(
+        &self,
+        grid: &mut DungeonGrid,
+        room_templates: &RoomTemplates,
+    ) -> Result<(), PinnedLayoutError> {
+        let room = room_templates
+            .by_identifier(&self.identifier)
+            .ok_or_else(|| PinnedLayoutError::UnknownRoom {
+                letter: self.letter,
+                identifier: self.identifier.clone(),
+            })?;
+
+        let footprint = match &room.footprint {
+            Some(footprint) => footprint.clone(),
+            None => Footprint::from_segments(IVec2::ONE, |wall, _| room.code & wall != 0)
+                .expect("a single cell is never too large"),
+        };
+
+        let mut covered: Vec<IVec2> = footprint
+            .cells()
+            .map(|(offset, _)| self.anchor + offset)
+            .collect();
+        let mut cells = self.cells.clone();
+        covered.sort_by_key(|cell| (cell.x, cell.y));
+        cells.sort_by_key(|cell| (cell.x, cell.y));
+
+        if covered != cells {
+            return Err(PinnedLayoutError::WrongShape {
+                identifier: self.identifier.clone(),
+                size: footprint.size(),
+            });
+        }
+
+        if !grid.fits(self.anchor, &footprint) {
+            return Err(PinnedLayoutError::DoesNotFit {
+                identifier: self.identifier.clone(),
+                anchor: self.anchor,
+            });
+        }
+
+        if footprint.is_single_cell() {
+            grid.place(
+                self.anchor,
+                GridCell {
+                    code: room.code,
+                    anchor: self.anchor,
+                    template: Some(room.iid),
+                    role: room.role,
+                    level: CellLevel::Unloaded,
+                },
+            );
+        } else {
+            grid.place_room(self.anchor, &footprint, Some(room.iid), CellLevel::Unloaded);
+        }
+
+        Ok(())
+    }
+}
+
+/// Reasons a layout can't be read, or one of its rooms can't be pinned.
+#[derive(Debug, thiserror::Error)]
+pub enum PinnedLayoutError {
+    #[error("could not read layout: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("layout is not UTF-8: {0}")]
+    Utf8(#[from] std::str::Utf8Error),
+    #[error("line {line} of the legend should be a single letter, `=`, and a level identifier")]
+    BadLegend { line: usize },
+    #[error("the map has no start hall, marked with `{START_HALL}`")]
+    NoStartHall,
+    #[error("the map has more than one start hall")]
+    SeveralStartHalls,
+    #[error("the letter {letter} is not in the legend")]
+    UnknownLetter { letter: char },
+    #[error("there is no room {identifier}, pinned by the letter {letter}")]
+    UnknownRoom { letter: char, identifier: String },
+    #[error("{identifier} should cover {} by {} cells", size.x, size.y)]
+    WrongShape { identifier: String, size: IVec2 },
+    #[error("{identifier} doesn't fit at {anchor}, its doors don't match the rooms around it")]
+    DoesNotFit { identifier: String, anchor: IVec2 },
+}
+
+/// Loads a [PinnedLayout] from a `.layout` file.
+#[derive(Default, TypePath)]
+pub struct PinnedLayoutLoader;
+
+impl AssetLoader for PinnedLayoutLoader {
+    type Asset = PinnedLayout;
+    type Settings = ();
+    type Error = PinnedLayoutError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &Self::Settings,
+        _load_context: &mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+
+        PinnedLayout::parse(std::str::from_utf8(&bytes)?)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["layout"]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::RandomGenerator;
+    use crate::layout::{DungeonSeed, ExploreLimit};
+
+    const PROJECT: &str = include_str!("../assets/ldtk/dungeon_of_madness.ldtk");
+    const TUTORIAL: &str = include_str!("../assets/layouts/tutorial.layout");
+
+    fn room_templates() -> RoomTemplates {
+        RoomTemplates::from_project_json(PROJECT.as_bytes())
+            .unwrap()
+            .0
+    }
+
+    #[test]
+    fn map_cells_are_relative_to_the_start_hall() {
+        let layout = PinnedLayout::parse("a = Arena\nb = Level_5\n\n.aa\nSaa\nb..\n").unwrap();
+
+        assert_eq!(
+            layout.pins,
+            [
+                Pin {
+                    letter: 'a',
+                    identifier: "Arena".into(),
+                    anchor: IVec2::new(1, 1),
+                    cells: vec![
+                        IVec2::new(1, 1),
+                        IVec2::new(2, 1),
+                        IVec2::new(1, 0),
+                        IVec2::new(2, 0)
+                    ],
+                },
+                Pin {
+                    letter: 'b',
+                    identifier: "Level_5".into(),
+                    anchor: IVec2::new(0, -1),
+                    cells: vec![IVec2::new(0, -1)],
+                },
+            ]
+        );
+
+        assert!(matches!(
+            PinnedLayout::parse("a = Arena\n.a."),
+            Err(PinnedLayoutError::NoStartHall)
+        ));
+        assert!(matches!(
+            PinnedLayout::parse("Sx"),
+            Err(PinnedLayoutError::UnknownLetter { letter: 'x' })
+        ));
+    }
+
+    #[test]
+    fn pinned_rooms_are_explored_around() {
+        let room_templates = room_templates();
+        let layout = PinnedLayout::parse(TUTORIAL).unwrap();
+
+        for seed in 0..20 {
+            let seed = DungeonSeed(seed);
+            let mut grid = DungeonGrid::default();
+            grid.place_start_hall(None, CellLevel::Unloaded);
+
+            let errors = layout.apply(&mut grid, &room_templates);
+            assert!(errors.is_empty(), "{errors:?}");
+
+            grid.explore(&seed, &RandomGenerator, ExploreLimit::Radius(4));
+
+            for pin in &layout.pins {
+                let iid = room_templates.by_identifier(&pin.identifier).unwrap().iid;
+
+                for cell in &pin.cells {
+                    assert_eq!(grid[cell].template, Some(iid));
+                }
+            }
+
+            for (cell, grid_cell) in grid.iter() {
+                assert!(grid.neighbours(*cell).agrees(grid_cell.code), "{cell}");
+            }
+        }
+    }
+
+    #[test]
+    fn rooms_which_do_not_fit_are_left_out() {
+        let room_templates = room_templates();
+        let mut grid = DungeonGrid::default();
+        grid.place_start_hall(None, CellLevel::Unloaded);
+
+        // Level_1 is closed to the north, so it can't sit below the start hall.
+        let layout =
+            PinnedLayout::parse("a = Level_1\nb = Tall_Hall\nc = Nowhere\nS\na\n.b\n.b\nc")
+                .unwrap();
+        let errors = layout.apply(&mut grid, &room_templates);
+
+        assert!(matches!(
+            errors[..],
+            [
+                PinnedLayoutError::DoesNotFit { .. },
+                PinnedLayoutError::UnknownRoom { letter: 'c', .. }
+            ]
+        ));
+        assert_eq!(grid.len(), 3);
+    }
+}
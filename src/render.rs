@@ -0,0 +1,406 @@
+//! Drawing a laid out dungeon into a single image on the CPU, from the tile
+//! layers of its rooms' levels, for the `render_layout` tool. No window or GPU
+//! is needed, so it runs on CI machines.
+//!
+//! Each level is filled with its background colour, and its tile layers are
+//! drawn bottom first, as LDtk draws them. Only the layers drawn from the
+//! dungeon tileset are drawn, so entities are left out. Cells without a room
+//! are left transparent.
+
+use bevy::prelude::*;
+use serde::Deserialize;
+
+use crate::layout::LEVEL_SIZE;
+use crate::room_templates::DUNGEON_WORLD;
+
+/// The file name of the tileset the rooms are drawn from.
+pub const TILESET_FILE: &str = "Dungeon_Tileset_v2.png";
+
+/// Where the stairs down are drawn from in the tileset.
+pub const STAIRS_TILE: Rect = Rect {
+    min: Vec2::new(64.0, 144.0),
+    max: Vec2::new(80.0, 160.0),
+};
+
+/// An image held as rows of RGBA pixels, top row first, with 8 bits per
+/// channel.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RgbaImage {
+    pub size: UVec2,
+    pub pixels: Vec<u8>,
+}
+
+impl RgbaImage {
+    /// A transparent image of the given size.
+    pub fn new(size: UVec2) -> Self {
+        Self {
+            size,
+            pixels: vec![0; (size.x * size.y * 4) as usize],
+        }
+    }
+
+    /// The pixel at the given position, or `None` if it's outside the image.
+    pub fn pixel(&self, position: IVec2) -> Option<[u8; 4]> {
+        let index = self.index(position)?;
+        Some(
+            self.pixels[index..index + 4]
+                .try_into()
+                .expect("a pixel is 4 bytes"),
+        )
+    }
+
+    /// Draw a colour over the pixel at the given position, scaled by the given
+    /// opacity. Positions outside the image are ignored.
+    fn blend(&mut self, position: IVec2, color: [u8; 4], opacity: f32) {
+        let Some(index) = self.index(position) else {
+            return;
+        };
+
+        let alpha = color[3] as f32 / 255.0 * opacity;
+        let under = &mut self.pixels[index..index + 4];
+        let under_alpha = under[3] as f32 / 255.0;
+        let out_alpha = alpha + under_alpha * (1.0 - alpha);
+
+        if out_alpha <= 0.0 {
+            return;
+        }
+
+        for channel in 0..3 {
+            let over = color[channel] as f32 * alpha;
+            let below = under[channel] as f32 * under_alpha * (1.0 - alpha);
+            under[channel] = ((over + below) / out_alpha).round() as u8;
+        }
+        under[3] = (out_alpha * 255.0).round() as u8;
+    }
+
+    fn index(&self, position: IVec2) -> Option<usize> {
+        let position = UVec2::try_from(position).ok()?;
+        (position.x < self.size.x && position.y < self.size.y)
+            .then(|| ((position.y * self.size.x + position.x) * 4) as usize)
+    }
+}
+
+/// A tile drawn from the tileset.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Tile {
+    /// Where the tile is drawn in the level, from its top left corner.
+    position: IVec2,
+    /// Where the tile is drawn from in the tileset.
+    source: IVec2,
+    size: i32,
+    /// Whether the tile is flipped along each axis.
+    flip: BVec2,
+    opacity: f32,
+}
+
+/// What's drawn in a level: its background, and its tiles, bottom first.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LevelTiles {
+    /// How many pixels across and down the level is.
+    pub size: IVec2,
+    background: [u8; 4],
+    tiles: Vec<Tile>,
+}
+
+impl LevelTiles {
+    /// Draw the level onto an image, with its top left corner at the given
+    /// position.
+    fn draw(&self, image: &mut RgbaImage, tileset: &RgbaImage, position: IVec2) {
+        for y in 0..self.size.y {
+            for x in 0..self.size.x {
+                image.blend(position + IVec2::new(x, y), self.background, 1.0);
+            }
+        }
+
+        for tile in &self.tiles {
+            draw_tile(
+                image,
+                tileset,
+                position + tile.position,
+                tile.source,
+                tile.size,
+                tile.flip,
+                tile.opacity,
+            );
+        }
+    }
+}
+
+/// The [LevelTiles] of every level in the `Dungeon` world of an LDtk project,
+/// by level identifier.
+#[derive(Clone, Debug, Default)]
+pub struct ProjectTiles {
+    levels: Vec<(String, LevelTiles)>,
+}
+
+impl ProjectTiles {
+    /// Read the tiles of every level from the contents of an LDtk project
+    /// file.
+    pub fn from_project_json(bytes: &[u8]) -> Result<Self, serde_json::Error> {
+        let project: ProjectJson = serde_json::from_slice(bytes)?;
+
+        let levels = project
+            .worlds
+            .into_iter()
+            .filter(|world| world.identifier == DUNGEON_WORLD)
+            .flat_map(|world| world.levels)
+            .map(|level| {
+                let tiles = level
+                    .layer_instances
+                    .iter()
+                    .rev()
+                    .filter(|layer| {
+                        layer.visible
+                            && layer
+                                .tileset_rel_path
+                                .as_deref()
+                                .is_some_and(|path| path.ends_with(TILESET_FILE))
+                    })
+                    .flat_map(|layer| {
+                        layer
+                            .auto_layer_tiles
+                            .iter()
+                            .chain(&layer.grid_tiles)
+                            .map(|tile| Tile {
+                                position: IVec2::from(tile.px),
+                                source: IVec2::from(tile.src),
+                                size: layer.grid_size,
+                                flip: BVec2::new(tile.f & 1 != 0, tile.f & 2 != 0),
+                                opacity: tile.a * layer.opacity,
+                            })
+                    })
+                    .collect();
+
+                let tiles = LevelTiles {
+                    size: IVec2::new(level.px_wid, level.px_hei),
+                    background: parse_color(&level.bg_color).unwrap_or_default(),
+                    tiles,
+                };
+
+                (level.identifier, tiles)
+            })
+            .collect();
+
+        Ok(Self { levels })
+    }
+
+    /// The tiles of the level with the given identifier.
+    pub fn level(&self, identifier: &str) -> Option<&LevelTiles> {
+        self.levels
+            .iter()
+            .find(|(level, _)| level == identifier)
+            .map(|(_, tiles)| tiles)
+    }
+}
+
+/// A room to draw, spawned from a level.
+#[derive(Clone, Copy, Debug)]
+pub struct RenderedRoom<'a> {
+    /// The top left cell of the room.
+    pub anchor: IVec2,
+    pub tiles: &'a LevelTiles,
+}
+
+/// Draw the given rooms into a single image, just large enough to hold them,
+/// with stairs down in the middle of each of the given cells. North is up.
+///
+/// Returns `None` if there are no rooms.
+pub fn render(rooms: &[RenderedRoom], stairs: &[IVec2], tileset: &RgbaImage) -> Option<RgbaImage> {
+    let cell_size = LEVEL_SIZE as i32;
+
+    // The corners of the image in pixels, with y going down.
+    let corners = |anchor: IVec2, size: IVec2| {
+        let top_left = anchor * IVec2::new(cell_size, -cell_size);
+        (top_left, top_left + size)
+    };
+    let (min, max) = rooms
+        .iter()
+        .map(|room| corners(room.anchor, room.tiles.size))
+        .reduce(|(min, max), (top_left, bottom_right)| {
+            (min.min(top_left), max.max(bottom_right))
+        })?;
+
+    let mut image = RgbaImage::new((max - min).as_uvec2());
+
+    for room in rooms {
+        let (top_left, _) = corners(room.anchor, room.tiles.size);
+        room.tiles.draw(&mut image, tileset, top_left - min);
+    }
+
+    let stairs_size = STAIRS_TILE.size().as_ivec2();
+    for cell in stairs {
+        let (top_left, _) = corners(*cell, IVec2::ZERO);
+        let middle = top_left - min + IVec2::splat(cell_size / 2);
+
+        draw_tile(
+            &mut image,
+            tileset,
+            middle - stairs_size / 2,
+            STAIRS_TILE.min.as_ivec2(),
+            stairs_size.x,
+            BVec2::FALSE,
+            1.0,
+        );
+    }
+
+    Some(image)
+}
+
+/// Draw a square tile from the tileset onto an image.
+fn draw_tile(
+    image: &mut RgbaImage,
+    tileset: &RgbaImage,
+    position: IVec2,
+    source: IVec2,
+    size: i32,
+    flip: BVec2,
+    opacity: f32,
+) {
+    for y in 0..size {
+        for x in 0..size {
+            let from = IVec2::new(
+                if flip.x { size - 1 - x } else { x },
+                if flip.y { size - 1 - y } else { y },
+            );
+
+            if let Some(color) = tileset.pixel(source + from) {
+                image.blend(position + IVec2::new(x, y), color, opacity);
+            }
+        }
+    }
+}
+
+/// Parse a colour written as `#RRGGBB`, as LDtk writes them.
+fn parse_color(text: &str) -> Option<[u8; 4]> {
+    let hex = text.strip_prefix('#').filter(|hex| hex.len() == 6)?;
+    let channel = |index: usize| u8::from_str_radix(hex.get(index..index + 2)?, 16).ok();
+
+    Some([channel(0)?, channel(2)?, channel(4)?, 255])
+}
+
+// Just enough of the LDtk project JSON format to read the tile layers.
+
+#[derive(Deserialize)]
+struct ProjectJson {
+    worlds: Vec<WorldJson>,
+}
+
+#[derive(Deserialize)]
+struct WorldJson {
+    identifier: String,
+    levels: Vec<LevelJson>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LevelJson {
+    identifier: String,
+    px_wid: i32,
+    px_hei: i32,
+    #[serde(rename = "__bgColor")]
+    bg_color: String,
+    layer_instances: Vec<LayerInstanceJson>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LayerInstanceJson {
+    #[serde(rename = "__gridSize")]
+    grid_size: i32,
+    #[serde(rename = "__opacity")]
+    opacity: f32,
+    #[serde(rename = "__tilesetRelPath")]
+    tileset_rel_path: Option<String>,
+    visible: bool,
+    auto_layer_tiles: Vec<TileJson>,
+    grid_tiles: Vec<TileJson>,
+}
+
+#[derive(Deserialize)]
+struct TileJson {
+    px: [i32; 2],
+    src: [i32; 2],
+    f: u8,
+    a: f32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROJECT: &str = include_str!("../assets/ldtk/dungeon_of_madness.ldtk");
+
+    /// A tileset whose every pixel holds its own position in red and green.
+    fn tileset() -> RgbaImage {
+        let mut tileset = RgbaImage::new(UVec2::splat(160));
+
+        for y in 0..160 {
+            for x in 0..160 {
+                let index = ((y * 160 + x) * 4) as usize;
+                tileset.pixels[index..index + 4].copy_from_slice(&[x as u8, y as u8, 0, 255]);
+            }
+        }
+
+        tileset
+    }
+
+    #[test]
+    fn rooms_are_drawn_at_their_cells() {
+        let project = ProjectTiles::from_project_json(PROJECT.as_bytes()).unwrap();
+        let start_hall = project.level("Start_Hall").unwrap();
+        let long_hall = project.level("Long_Hall").unwrap();
+        let tileset = tileset();
+
+        // The long hall spans two cells, to the right of and below the start
+        // hall.
+        let rooms = [
+            RenderedRoom {
+                anchor: IVec2::ZERO,
+                tiles: start_hall,
+            },
+            RenderedRoom {
+                anchor: IVec2::new(1, -1),
+                tiles: long_hall,
+            },
+        ];
+        let image = render(&rooms, &[IVec2::new(2, -1)], &tileset).unwrap();
+        let cell_size = LEVEL_SIZE as i32;
+
+        assert_eq!(image.size, UVec2::new(3, 2) * cell_size as u32);
+
+        // The cell below the start hall is empty.
+        assert_eq!(image.pixel(IVec2::new(10, cell_size + 10)), Some([0; 4]));
+
+        // The start hall's floor is drawn, opaque.
+        let floor = image.pixel(IVec2::splat(cell_size / 2 + 4)).unwrap();
+        assert_eq!(floor[3], 255);
+
+        // The middle of the stairs is drawn straight from the tileset.
+        let stairs = IVec2::new(cell_size * 5 / 2, cell_size * 3 / 2);
+        assert_eq!(
+            image.pixel(stairs),
+            tileset.pixel(STAIRS_TILE.center().as_ivec2())
+        );
+
+        assert!(render(&[], &[], &tileset).is_none());
+    }
+
+    #[test]
+    fn flipped_tiles_are_mirrored() {
+        let tileset = tileset();
+        let mut image = RgbaImage::new(UVec2::splat(16));
+
+        draw_tile(
+            &mut image,
+            &tileset,
+            IVec2::ZERO,
+            IVec2::new(32, 64),
+            16,
+            BVec2::new(true, false),
+            1.0,
+        );
+
+        assert_eq!(image.pixel(IVec2::new(0, 0)), Some([47, 64, 0, 255]));
+        assert_eq!(image.pixel(IVec2::new(15, 3)), Some([32, 67, 0, 255]));
+    }
+}
@@ -0,0 +1,144 @@
+//! Special rooms, which give each floor something to find.
+//!
+//! A level in the LDtk project takes on a role through its `Role` level field.
+//! Rooms with a role are never picked by their doors alone. Instead, each
+//! time a room is placed, the [RoleRule] of every role is checked in turn, and
+//! the first role whose rule allows the cell is given to the room.
+
+use std::fmt;
+
+/// The special role of a room.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RoomRole {
+    Treasure,
+    Shrine,
+    Boss,
+    /// The way off the floor. Exit rooms have stairs down.
+    Exit,
+}
+
+impl RoomRole {
+    /// Every role, in the order their rules are checked. The rarest come
+    /// first, so they aren't crowded out by the common ones.
+    pub const ALL: [RoomRole; 4] = [
+        RoomRole::Exit,
+        RoomRole::Boss,
+        RoomRole::Treasure,
+        RoomRole::Shrine,
+    ];
+
+    /// The role named by the `Role` level field, if it's one we know.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|role| role.name() == name)
+    }
+
+    /// The name used for this role in the `Role` level field.
+    pub fn name(self) -> &'static str {
+        match self {
+            RoomRole::Treasure => "Treasure",
+            RoomRole::Shrine => "Shrine",
+            RoomRole::Boss => "Boss",
+            RoomRole::Exit => "Exit",
+        }
+    }
+
+    /// Where rooms with this role may be placed.
+    pub fn rule(self) -> RoleRule {
+        match self {
+            RoomRole::Treasure => RoleRule {
+                min_distance: 3,
+                max_per_floor: 3,
+                dead_end_only: true,
+                chance: 3,
+            },
+            RoomRole::Shrine => RoleRule {
+                min_distance: 2,
+                max_per_floor: 2,
+                dead_end_only: false,
+                chance: 6,
+            },
+            RoomRole::Boss => RoleRule {
+                min_distance: 6,
+                max_per_floor: 1,
+                dead_end_only: true,
+                chance: 2,
+            },
+            RoomRole::Exit => RoleRule {
+                min_distance: 8,
+                max_per_floor: 1,
+                dead_end_only: true,
+                chance: 2,
+            },
+        }
+    }
+
+    fn index(self) -> usize {
+        match self {
+            RoomRole::Treasure => 0,
+            RoomRole::Shrine => 1,
+            RoomRole::Boss => 2,
+            RoomRole::Exit => 3,
+        }
+    }
+}
+
+impl fmt::Display for RoomRole {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// The placement rules for a [RoomRole].
+#[derive(Clone, Copy, Debug)]
+pub struct RoleRule {
+    /// How many cells from the start hall the room must be at least, measured
+    /// per axis.
+    pub min_distance: i32,
+    /// How many rooms with this role a single floor may have.
+    pub max_per_floor: usize,
+    /// Whether the room may only be placed where it has a single door.
+    pub dead_end_only: bool,
+    /// One in this many cells which pass the other rules gets the role.
+    pub chance: u32,
+}
+
+impl RoleRule {
+    /// Whether a room with the given door code may take the role in `cell`,
+    /// with `placed` rooms of this role already on the floor. The chance is
+    /// left to the caller.
+    pub fn allows(&self, cell_distance: i32, code: u16, placed: usize) -> bool {
+        cell_distance >= self.min_distance
+            && placed < self.max_per_floor
+            && (!self.dead_end_only || is_dead_end(code))
+    }
+}
+
+/// Whether a room with the given door code has a single door.
+pub fn is_dead_end(code: u16) -> bool {
+    (code & 0xF).count_ones() == 3
+}
+
+/// Which door codes there is a room for, for each role.
+///
+/// A role is only given to a room whose door code has a room with that role,
+/// so a project doesn't need a room for every role and every set of doors.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RoleCodes([u16; 4]);
+
+impl RoleCodes {
+    /// Every door code for every role, for laying out dungeons without a
+    /// project to pick rooms from.
+    pub fn all() -> Self {
+        Self([u16::MAX; 4])
+    }
+
+    /// Note that there is a room with the given role and door code.
+    pub fn insert(&mut self, role: RoomRole, code: u16) {
+        self.0[role.index()] |= 1 << (code & 0xF);
+    }
+
+    /// Whether there is a room with the given role and door code.
+    pub fn contains(&self, role: RoomRole, code: u16) -> bool {
+        self.0[role.index()] & (1 << (code & 0xF)) != 0
+    }
+}
@@ -0,0 +1,591 @@
+//! The rooms the dungeon is built from, read from the levels of the LDtk
+//! project and their custom level fields.
+//!
+//! The doors of each level in the `Dungeon` world are where the `wall` cells of
+//! its IntGrid layer leave a gap along its edges, see [walls](crate::walls).
+//! There's nothing to declare and no naming convention to follow: a level is
+//! usable as soon as it's drawn.
+//!
+//! Any number of levels may share the same doors. They are variants of each
+//! other, and one is picked at random in proportion to its `Weight` level
+//! field, an `Int` which defaults to 1 when missing.
+//!
+//! A level with its `Role` level field set is a special room, see
+//! [roles](crate::roles). It is only picked for rooms given that role.
+//!
+//! A level may span several grid cells, as long as it's a whole number of
+//! cells across and down, see [footprints](crate::footprints). It has a door
+//! wherever the walls have a gap along each cell's stretch of its edges. Rooms
+//! spanning several cells never take on a role.
+//!
+//! Levels may still have the `Bool` level fields `DoorUp`, `DoorRight`,
+//! `DoorDown` and `DoorLeft`, and `DoorUp2` and so on for the second cell
+//! along an edge, as notes for designers. They're ignored here, and the
+//! `lint_project` tool checks they agree with the walls, see
+//! [lint](crate::lint).
+//!
+//! A level with its `Theme` level field set is preferred in the parts of the
+//! dungeon with that theme, see [themes](crate::themes).
+//!
+//! Levels may also come in mirrored copies, written into the project by the
+//! `mirror_rooms` tool, see [mirror](crate::mirror). The copies are rooms like
+//! any other.
+
+use bevy::asset::io::Reader;
+use bevy::asset::{AssetLoader, LoadContext};
+use bevy::prelude::*;
+use serde::Deserialize;
+use tinyrand::Rand;
+
+use crate::footprints::{Footprint, MAX_ROOM_SIZE};
+use crate::layout::{
+    DungeonSeed, LEVEL_SIZE, VARIANT_STREAM, WALL_DOWN, WALL_LEFT, WALL_RIGHT, WALL_UP,
+};
+use crate::ldtk_json::fnv1a;
+use crate::roles::{RoleCodes, RoomRole};
+use crate::walls::{LayerDefJson, LayerInstanceJson, WallGrid, WallGridError, WallValues};
+
+/// The LDtk project the game loads, as an asset path.
+pub const PROJECT_FILE: &str = "ldtk/dungeon_of_madness.ldtk";
+
+/// Where the [PROJECT_FILE] is relative to the root of the repository, which
+/// is where the tools read it from, and where floors are exported to.
+pub fn project_path() -> String {
+    format!("assets/{PROJECT_FILE}")
+}
+
+/// The LDtk world which holds the room templates.
+pub const DUNGEON_WORLD: &str = "Dungeon";
+
+/// The level every floor starts in, which is never picked for other rooms.
+pub const START_HALL: &str = "Start_Hall";
+
+/// The optional level fields noting the doors of a room, one for each segment
+/// of each edge, along with the wall bit which is set when the field is
+/// `false`.
+pub(crate) const DOOR_FIELDS: [([&str; MAX_ROOM_SIZE.x as usize], u16); 4] = [
+    (["DoorUp", "DoorUp2"], WALL_UP),
+    (["DoorRight", "DoorRight2"], WALL_RIGHT),
+    (["DoorDown", "DoorDown2"], WALL_DOWN),
+    (["DoorLeft", "DoorLeft2"], WALL_LEFT),
+];
+
+/// The level field holding the relative weight of a room among its variants.
+const WEIGHT_FIELD: &str = "Weight";
+
+/// The level field holding the [RoomRole] of a special room.
+const ROLE_FIELD: &str = "Role";
+
+/// The level field holding the name of the theme a room belongs to.
+const THEME_FIELD: &str = "Theme";
+
+/// A level from the LDtk project which can be placed in the dungeon.
+#[derive(Clone, Debug)]
+pub struct RoomTemplate {
+    /// The LDtk level identifier, used to build the asset path.
+    pub identifier: String,
+    /// The LDtk level iid, as a `u128` so it can be compared against the
+    /// values from the `iid!` macro.
+    pub iid: u128,
+    /// Which walls of the room are closed, as a combination of [WALL_UP],
+    /// [WALL_RIGHT], [WALL_DOWN], and [WALL_LEFT]. For a room spanning several
+    /// cells, the walls of its top left cell.
+    pub code: u16,
+    /// The cells covered by a room spanning several cells, and their doors, or
+    /// `None` for a room filling a single cell.
+    pub footprint: Option<Footprint>,
+    /// How likely this room is to be picked over other rooms with the same
+    /// code. A weight of zero means it is never picked.
+    pub weight: u32,
+    /// The role of a special room, or `None` for a plain room.
+    pub role: Option<RoomRole>,
+    /// The name of the theme this room belongs to, or `None` if it fits in
+    /// anywhere.
+    pub theme: Option<String>,
+    /// A hash of the level's JSON, which changes whenever the level is edited,
+    /// so the rooms spawned from it can be respawned when the project is
+    /// reloaded.
+    pub revision: u64,
+}
+
+/// Every [RoomTemplate] in the `Dungeon` world of an LDtk project.
+#[derive(Asset, TypePath, Clone, Debug, Default)]
+pub struct RoomTemplates {
+    pub rooms: Vec<RoomTemplate>,
+}
+
+impl RoomTemplates {
+    /// Read the room templates from the contents of an LDtk project file.
+    ///
+    /// Levels with missing walls or invalid level fields are left out, and returned
+    /// alongside the templates so they can be reported.
+    pub fn from_project_json(
+        bytes: &[u8],
+    ) -> Result<(Self, Vec<RoomTemplateError>), serde_json::Error> {
+        let project: ProjectJson = serde_json::from_slice(bytes)?;
+        let wall_values = WallValues::new(&project.defs.layers);
+
+        let mut rooms = Vec::new();
+        let mut errors = Vec::new();
+
+        for level in project
+            .worlds
+            .into_iter()
+            .filter(|world| world.identifier == DUNGEON_WORLD)
+            .flat_map(|world| world.levels)
+        {
+            let revision = fnv1a(&level.to_string());
+            let level = LevelJson::deserialize(level)?;
+
+            match RoomTemplate::from_level_json(level, revision, &wall_values) {
+                Ok(room) => rooms.push(room),
+                Err(error) => errors.push(error),
+            }
+        }
+
+        Ok((Self { rooms }, errors))
+    }
+
+    /// The room template with the given iid.
+    pub fn by_iid(&self, iid: u128) -> Option<&RoomTemplate> {
+        self.rooms.iter().find(|room| room.iid == iid)
+    }
+
+    /// The room template with the given LDtk level identifier.
+    pub fn by_identifier(&self, identifier: &str) -> Option<&RoomTemplate> {
+        self.rooms.iter().find(|room| room.identifier == identifier)
+    }
+
+    /// Every room template filling a single cell with the given door code.
+    pub fn variants(&self, code: u16) -> impl Iterator<Item = &RoomTemplate> {
+        self.rooms
+            .iter()
+            .filter(move |room| room.footprint.is_none() && room.code == code)
+    }
+
+    /// The rooms which may be picked for a room with the given door code and
+    /// role, in a part of the dungeon with the given theme.
+    ///
+    /// Rooms tagged with the theme are preferred. If there are none, the rooms
+    /// without a theme are used instead, and failing those, the rooms of any
+    /// other theme.
+    pub fn candidates(&self, code: u16, role: Option<RoomRole>, theme: &str) -> Vec<&RoomTemplate> {
+        prefer_theme(
+            self.variants(code)
+                .filter(move |room| room.role == role && room.weight > 0),
+            theme,
+        )
+    }
+
+    /// The rooms which may be picked for a room spanning several cells with
+    /// the given footprint, in a part of the dungeon with the given theme.
+    /// Themes are preferred as for [RoomTemplates::candidates].
+    pub fn footprint_candidates(&self, footprint: &Footprint, theme: &str) -> Vec<&RoomTemplate> {
+        prefer_theme(
+            self.rooms
+                .iter()
+                .filter(|room| room.footprint.as_ref() == Some(footprint) && room.weight > 0),
+            theme,
+        )
+    }
+
+    /// Pick the room for a room of the dungeon, the way the game does: one of
+    /// the [RoomTemplates::candidates], or the
+    /// [RoomTemplates::footprint_candidates] for a room spanning several cells,
+    /// at random from the [VARIANT_STREAM] of its top left cell.
+    ///
+    /// The start hall is a one-off, so it's never picked.
+    pub fn pick(
+        &self,
+        seed: &DungeonSeed,
+        anchor: IVec2,
+        code: u16,
+        role: Option<RoomRole>,
+        footprint: Option<&Footprint>,
+        theme: &str,
+    ) -> Option<&RoomTemplate> {
+        let candidates = match footprint {
+            Some(footprint) if !footprint.is_single_cell() => {
+                self.footprint_candidates(footprint, theme)
+            }
+            _ => self.candidates(code, role, theme),
+        };
+
+        pick_weighted(
+            candidates
+                .into_iter()
+                .filter(|room| room.identifier != START_HALL),
+            &mut seed.cell_rand(anchor, VARIANT_STREAM),
+        )
+    }
+
+    /// The door codes there is a special room for, for each role.
+    pub fn role_codes(&self) -> RoleCodes {
+        let mut role_codes = RoleCodes::default();
+
+        for room in self.rooms.iter().filter(|room| room.footprint.is_none()) {
+            if let Some(role) = room.role {
+                role_codes.insert(role, room.code);
+            }
+        }
+
+        role_codes
+    }
+
+    /// The footprint of every room spanning several cells which may be
+    /// picked.
+    pub fn footprints(&self) -> Vec<Footprint> {
+        let mut footprints = Vec::new();
+
+        for footprint in self
+            .rooms
+            .iter()
+            .filter(|room| room.weight > 0)
+            .filter_map(|room| room.footprint.as_ref())
+        {
+            if !footprints.contains(footprint) {
+                footprints.push(footprint.clone());
+            }
+        }
+
+        footprints
+    }
+}
+
+/// The rooms tagged with the given theme, or the rooms without a theme if
+/// there are none. If every room belongs to another theme, they're all kept,
+/// as a room out of place beats a hole in the dungeon.
+fn prefer_theme<'a>(
+    rooms: impl IntoIterator<Item = &'a RoomTemplate>,
+    theme: &str,
+) -> Vec<&'a RoomTemplate> {
+    let rooms: Vec<_> = rooms.into_iter().collect();
+
+    for preferred in [Some(theme), None] {
+        let matching: Vec<_> = rooms
+            .iter()
+            .copied()
+            .filter(|room| room.theme.as_deref() == preferred)
+            .collect();
+
+        if !matching.is_empty() {
+            return matching;
+        }
+    }
+
+    rooms
+}
+
+/// Pick one of the given room templates at random, in proportion to their
+/// weights.
+///
+/// Returns `None` if there are no templates, or they all have a weight of
+/// zero.
+pub fn pick_weighted<'a>(
+    rooms: impl IntoIterator<Item = &'a RoomTemplate>,
+    rand: &mut impl Rand,
+) -> Option<&'a RoomTemplate> {
+    let rooms: Vec<_> = rooms.into_iter().filter(|room| room.weight > 0).collect();
+
+    let total_weight = rooms.iter().map(|room| room.weight as u64).sum();
+    if total_weight == 0 {
+        return None;
+    }
+
+    let mut pick = rand.next_lim_u64(total_weight);
+
+    rooms.into_iter().find(|room| {
+        let weight = room.weight as u64;
+        if pick < weight {
+            true
+        } else {
+            pick -= weight;
+            false
+        }
+    })
+}
+
+impl RoomTemplate {
+    /// The door code of the given cell of the room, as its offset from the top
+    /// left cell in grid coordinates, or `None` if the room doesn't cover it.
+    pub fn cell_code(&self, offset: IVec2) -> Option<u16> {
+        match &self.footprint {
+            Some(footprint) => footprint
+                .cells()
+                .find(|(cell, _)| *cell == offset)
+                .map(|(_, code)| code),
+            None => (offset == IVec2::ZERO).then_some(self.code),
+        }
+    }
+
+    fn from_level_json(
+        level: LevelJson,
+        revision: u64,
+        wall_values: &WallValues,
+    ) -> Result<Self, RoomTemplateError> {
+        let cell_size = LEVEL_SIZE as i32;
+        let invalid_size = || RoomTemplateError::InvalidSize {
+            level: level.identifier.clone(),
+            width: level.px_wid,
+            height: level.px_hei,
+        };
+
+        if level.px_wid % cell_size != 0 || level.px_hei % cell_size != 0 {
+            return Err(invalid_size());
+        }
+
+        let size = IVec2::new(level.px_wid, level.px_hei) / cell_size;
+        let walls =
+            WallGrid::from_layers(&level.layer_instances, wall_values, size).map_err(|error| {
+                RoomTemplateError::Walls {
+                    level: level.identifier.clone(),
+                    error,
+                }
+            })?;
+
+        let footprint =
+            Footprint::from_segments(size, |wall, segment| !walls.has_gap(size, wall, segment))
+                .ok_or_else(invalid_size)?;
+
+        let code = footprint.cells().next().map_or(0, |(_, code)| code);
+        let footprint = (!footprint.is_single_cell()).then_some(footprint);
+
+        let weight = match level
+            .field_instances
+            .iter()
+            .find(|field| field.identifier == WEIGHT_FIELD)
+        {
+            None => 1,
+            Some(field) => field
+                .value
+                .as_u64()
+                .and_then(|weight| u32::try_from(weight).ok())
+                .ok_or_else(|| RoomTemplateError::InvalidField {
+                    level: level.identifier.clone(),
+                    field: WEIGHT_FIELD,
+                    value: field.value.to_string(),
+                })?,
+        };
+
+        let role =
+            match level
+                .field_instances
+                .iter()
+                .find(|field| field.identifier == ROLE_FIELD)
+                .map(|field| &field.value)
+            {
+                None | Some(serde_json::Value::Null) => None,
+                Some(value) => Some(value.as_str().and_then(RoomRole::from_name).ok_or_else(
+                    || RoomTemplateError::InvalidField {
+                        level: level.identifier.clone(),
+                        field: ROLE_FIELD,
+                        value: value.to_string(),
+                    },
+                )?),
+            };
+
+        let theme = match level
+            .field_instances
+            .iter()
+            .find(|field| field.identifier == THEME_FIELD)
+            .map(|field| &field.value)
+        {
+            None | Some(serde_json::Value::Null) => None,
+            Some(serde_json::Value::String(theme)) => Some(theme.clone()),
+            Some(value) => {
+                return Err(RoomTemplateError::InvalidField {
+                    level: level.identifier.clone(),
+                    field: THEME_FIELD,
+                    value: value.to_string(),
+                })
+            }
+        };
+
+        let iid = u128::from_str_radix(&level.iid.replace('-', ""), 16).map_err(|_| {
+            RoomTemplateError::InvalidIid {
+                level: level.identifier.clone(),
+                iid: level.iid.clone(),
+            }
+        })?;
+
+        Ok(Self {
+            identifier: level.identifier,
+            iid,
+            code,
+            footprint,
+            weight,
+            role,
+            theme,
+            revision,
+        })
+    }
+}
+
+/// Reasons a level in the LDtk project can't be used as a [RoomTemplate].
+#[derive(Debug, thiserror::Error)]
+pub enum RoomTemplateError {
+    #[error("level {level} has an invalid {field} level field: {value}")]
+    InvalidField {
+        level: String,
+        field: &'static str,
+        value: String,
+    },
+    #[error(
+        "level {level} is {width}x{height} pixels, rather than 1 or 2 cells of {} pixels \
+         across and down",
+        LEVEL_SIZE
+    )]
+    InvalidSize {
+        level: String,
+        width: i32,
+        height: i32,
+    },
+    #[error("level {level} has {error}")]
+    Walls { level: String, error: WallGridError },
+    #[error("level {level} has a malformed iid: {iid}")]
+    InvalidIid { level: String, iid: String },
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum RoomTemplatesLoaderError {
+    #[error("could not read LDtk project: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("could not parse LDtk project: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+/// Loads [RoomTemplates] from an `.ldtk` project file.
+///
+/// Levels which can't be used are reported in the log, rather than failing
+/// the whole load.
+///
+/// It claims no file extensions, so it's only picked when [RoomTemplates] are
+/// asked for by type. The `.ldtk` extension, and the labeled levels and
+/// worlds within, are left to shieldtank's loader.
+#[derive(Default, TypePath)]
+pub struct RoomTemplatesLoader;
+
+impl AssetLoader for RoomTemplatesLoader {
+    type Asset = RoomTemplates;
+    type Settings = ();
+    type Error = RoomTemplatesLoaderError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &Self::Settings,
+        load_context: &mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+
+        let (room_templates, errors) = RoomTemplates::from_project_json(&bytes)?;
+
+        for error in errors {
+            error!("Skipping room in {}: {error}", load_context.path());
+        }
+
+        Ok(room_templates)
+    }
+}
+
+// Just enough of the LDtk project JSON format to read the room templates.
+
+#[derive(Deserialize)]
+struct ProjectJson {
+    defs: DefsJson,
+    worlds: Vec<WorldJson>,
+}
+
+#[derive(Deserialize)]
+struct DefsJson {
+    layers: Vec<LayerDefJson>,
+}
+
+#[derive(Deserialize)]
+struct WorldJson {
+    identifier: String,
+    /// Read as they are, so they can be hashed for their
+    /// [RoomTemplate::revision].
+    levels: Vec<serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LevelJson {
+    identifier: String,
+    iid: String,
+    px_wid: i32,
+    px_hei: i32,
+    field_instances: Vec<FieldInstanceJson>,
+    layer_instances: Vec<LayerInstanceJson>,
+}
+
+#[derive(Deserialize)]
+struct FieldInstanceJson {
+    #[serde(rename = "__identifier")]
+    identifier: String,
+    #[serde(rename = "__value")]
+    value: serde_json::Value,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROJECT: &str = include_str!("../assets/ldtk/dungeon_of_madness.ldtk");
+
+    #[test]
+    fn rooms_of_other_themes_are_picked_over_none() {
+        let (mut room_templates, _) = RoomTemplates::from_project_json(PROJECT.as_bytes()).unwrap();
+
+        let code = room_templates.by_identifier("Level_0").unwrap().code;
+        for room in &mut room_templates.rooms {
+            room.theme = Some(if room.code == code { "Abyss" } else { "Depths" }.into());
+        }
+
+        // The rooms with the code all belong to the abyss, but are picked in
+        // the entry all the same.
+        let candidates = room_templates.candidates(code, None, "Entry");
+        assert!(!candidates.is_empty());
+        assert!(candidates
+            .iter()
+            .all(|room| room.code == code && room.theme.as_deref() == Some("Abyss")));
+
+        let picked = room_templates
+            .pick(&DungeonSeed(7), IVec2::ONE, code, None, None, "Entry")
+            .unwrap();
+        assert_eq!(picked.code, code);
+
+        // Rooms of the theme asked for still come first.
+        room_templates.rooms[0].theme = Some("Entry".into());
+        let entry = room_templates.rooms[0].identifier.clone();
+        let code = room_templates.rooms[0].code;
+        let candidates = room_templates.candidates(code, None, "Entry");
+        assert!(candidates.iter().all(|room| room.identifier == entry));
+    }
+
+    #[test]
+    fn only_edited_levels_change_revision() {
+        let mut project: serde_json::Value = serde_json::from_str(PROJECT).unwrap();
+        project["worlds"][0]["levels"][0]["__bgColor"] = "#123456".into();
+        let edited = serde_json::to_vec(&project).unwrap();
+
+        let (before, _) = RoomTemplates::from_project_json(PROJECT.as_bytes()).unwrap();
+        let (after, _) = RoomTemplates::from_project_json(&edited).unwrap();
+
+        let changed: Vec<&str> = before
+            .rooms
+            .iter()
+            .filter(|room| after.by_iid(room.iid).unwrap().revision != room.revision)
+            .map(|room| room.identifier.as_str())
+            .collect();
+
+        assert_eq!(
+            changed,
+            [project["worlds"][0]["levels"][0]["identifier"]
+                .as_str()
+                .unwrap()]
+        );
+    }
+}
@@ -0,0 +1,194 @@
+//! Themed regions of the dungeon, so the deep parts feel different from the
+//! entry.
+//!
+//! Every cell belongs to one of the [THEMES], decided by the [ThemeMap]. A
+//! theme prefers the rooms tagged with its name in their `Theme` level field,
+//! and may bring its own clouds.
+
+use bevy::math::FloatExt;
+use bevy::prelude::*;
+use tinyrand::Rand as _;
+
+use crate::layout::{DungeonSeed, THEME_STREAM};
+
+/// How many cells apart the points of the noise field are. Larger values give
+/// larger regions.
+pub const THEME_NOISE_SCALE: i32 = 6;
+
+/// Cells this close to the start hall, measured per axis, always belong to
+/// the first theme, whatever the [ThemeMap].
+pub const ENTRY_RADIUS: i32 = 1;
+
+/// Every theme, from the entry of the dungeon to its deepest parts.
+pub const THEMES: [Theme; 3] = [
+    Theme {
+        name: "Entry",
+        min_distance: 0,
+        clouds: None,
+    },
+    Theme {
+        name: "Depths",
+        min_distance: 4,
+        clouds: Some(CloudsTheme {
+            speed: 0.03,
+            scale: Vec2::new(3.0, 10.0),
+            color_gain: 1.2,
+            color_offset: -0.35,
+            alpha: 0.4,
+        }),
+    },
+    Theme {
+        name: "Abyss",
+        min_distance: 9,
+        clouds: Some(CloudsTheme {
+            speed: 0.05,
+            scale: Vec2::new(4.0, 6.0),
+            color_gain: 0.9,
+            color_offset: -0.45,
+            alpha: 0.6,
+        }),
+    },
+];
+
+/// A region of the dungeon with its own rooms, and maybe its own clouds.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Theme {
+    /// The name rooms are tagged with in their `Theme` level field.
+    pub name: &'static str,
+    /// How many cells from the start hall, measured per axis, the theme
+    /// starts at when themes are mapped by distance.
+    pub min_distance: i32,
+    /// The clouds drawn over the theme, or `None` for the default clouds.
+    pub clouds: Option<CloudsTheme>,
+}
+
+/// The clouds parameters a [Theme] overrides.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CloudsTheme {
+    pub speed: f32,
+    pub scale: Vec2,
+    pub color_gain: f32,
+    pub color_offset: f32,
+    pub alpha: f32,
+}
+
+/// How cells are sorted into [THEMES].
+#[derive(Resource, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ThemeMap {
+    /// Themes come in rings around the start hall, by their
+    /// [min_distance](Theme::min_distance).
+    #[default]
+    Distance,
+    /// Themes come in patches, from a noise field drawn from the seed.
+    Noise,
+}
+
+impl ThemeMap {
+    /// Read from the `themes` launch option, `distance` or `noise`. Defaults
+    /// to [ThemeMap::Distance].
+    pub fn from_env() -> Self {
+        let Some(value) = crate::launch_option("themes") else {
+            return Self::default();
+        };
+
+        Self::by_name(&value).unwrap_or_else(|| {
+            warn!("Ignoring unknown themes: {value}. Expected distance or noise");
+            Self::default()
+        })
+    }
+
+    /// The theme map with the given name, `distance` or `noise`.
+    pub fn by_name(name: &str) -> Option<Self> {
+        match name {
+            "distance" => Some(Self::Distance),
+            "noise" => Some(Self::Noise),
+            _ => None,
+        }
+    }
+
+    /// The theme of the given cell.
+    pub fn theme(&self, seed: &DungeonSeed, cell: IVec2) -> &'static Theme {
+        let distance = cell.abs().max_element();
+
+        if distance <= ENTRY_RADIUS {
+            return &THEMES[0];
+        }
+
+        match self {
+            ThemeMap::Distance => THEMES
+                .iter()
+                .rev()
+                .find(|theme| distance >= theme.min_distance)
+                .unwrap_or(&THEMES[0]),
+            ThemeMap::Noise => {
+                let index = (noise(seed, cell) * THEMES.len() as f32) as usize;
+                &THEMES[index.min(THEMES.len() - 1)]
+            }
+        }
+    }
+}
+
+/// Smooth value noise in `0.0..1.0`, interpolated between random values on a
+/// lattice of points [THEME_NOISE_SCALE] cells apart.
+fn noise(seed: &DungeonSeed, cell: IVec2) -> f32 {
+    let scaled = cell.as_vec2() / THEME_NOISE_SCALE as f32;
+    let lattice = scaled.floor();
+    let t = scaled - lattice;
+    let t = t * t * (3.0 - 2.0 * t);
+    let lattice = lattice.as_ivec2();
+
+    let value = |offset: IVec2| {
+        seed.cell_rand(lattice + offset, THEME_STREAM).next_u32() as f32 / u32::MAX as f32
+    };
+
+    let bottom = value(IVec2::ZERO).lerp(value(IVec2::X), t.x);
+    let top = value(IVec2::Y).lerp(value(IVec2::ONE), t.x);
+
+    bottom.lerp(top, t.y).min(0.999_999)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn distance_themes_come_in_rings() {
+        let seed = DungeonSeed(0);
+
+        assert_eq!(ThemeMap::Distance.theme(&seed, IVec2::ZERO).name, "Entry");
+        assert_eq!(
+            ThemeMap::Distance.theme(&seed, IVec2::new(3, -2)).name,
+            "Entry"
+        );
+        assert_eq!(
+            ThemeMap::Distance.theme(&seed, IVec2::new(-4, 1)).name,
+            "Depths"
+        );
+        assert_eq!(
+            ThemeMap::Distance.theme(&seed, IVec2::new(2, 9)).name,
+            "Abyss"
+        );
+    }
+
+    #[test]
+    fn noise_themes_keep_the_entry_and_use_every_theme() {
+        for seed in 0..20 {
+            let seed = DungeonSeed(seed);
+
+            for cell in [IVec2::ZERO, IVec2::ONE, IVec2::NEG_ONE] {
+                assert_eq!(ThemeMap::Noise.theme(&seed, cell).name, "Entry");
+            }
+        }
+
+        let seed = DungeonSeed(1234);
+        for theme in &THEMES {
+            assert!(
+                (-60..60)
+                    .flat_map(|x| (-60..60).map(move |y| IVec2::new(x, y)))
+                    .any(|cell| ThemeMap::Noise.theme(&seed, cell) == theme),
+                "no cell with theme {}",
+                theme.name
+            );
+        }
+    }
+}
@@ -0,0 +1,204 @@
+//! The walls of a room, as drawn in the `wall` cells of its IntGrid layer.
+//!
+//! The IntGrid is what the player collides with, so a door is only really
+//! there if there's a gap in the walls along that segment of the room's edge.
+//! The door codes of the room templates are read from these gaps, see
+//! [room_templates](crate::room_templates).
+
+use bevy::prelude::*;
+use serde::Deserialize;
+
+use crate::doors::{WALL_DOWN, WALL_LEFT, WALL_RIGHT, WALL_UP};
+use crate::layout::LEVEL_SIZE;
+
+/// The identifier of the IntGrid value marking walls.
+pub const WALL_VALUE: &str = "wall";
+
+/// Which tiles of a level's IntGrid layer are walls.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WallGrid {
+    /// How many tiles across and down the IntGrid is.
+    size: IVec2,
+    /// Whether each tile is a wall, row by row from the top left.
+    walls: Vec<bool>,
+}
+
+impl WallGrid {
+    /// Read the walls from the values of an IntGrid layer, row by row from the
+    /// top left, as in its `intGridCsv`.
+    ///
+    /// Returns `None` if there isn't a value for every tile.
+    pub fn new(size: IVec2, values: &[i64], wall_value: i64) -> Option<Self> {
+        if size.min_element() < 1 || values.len() != (size.x * size.y) as usize {
+            return None;
+        }
+
+        Some(Self {
+            size,
+            walls: values.iter().map(|value| *value == wall_value).collect(),
+        })
+    }
+
+    /// How many tiles across and down the IntGrid is.
+    pub fn size(&self) -> IVec2 {
+        self.size
+    }
+
+    /// Whether the given segment of the given edge of the room has a gap in
+    /// its walls. The room is `cells` grid cells across and down, and its
+    /// edges have a segment for each, see [Footprint](crate::footprints).
+    pub fn has_gap(&self, cells: IVec2, wall: u16, segment: i32) -> bool {
+        let tiles = self.size / cells;
+        let last = self.size - 1;
+
+        let segment_tiles: Vec<IVec2> = match wall {
+            WALL_UP => (0..tiles.x)
+                .map(|x| IVec2::new(segment * tiles.x + x, 0))
+                .collect(),
+            WALL_RIGHT => (0..tiles.y)
+                .map(|y| IVec2::new(last.x, segment * tiles.y + y))
+                .collect(),
+            WALL_DOWN => (0..tiles.x)
+                .map(|x| IVec2::new(segment * tiles.x + x, last.y))
+                .collect(),
+            WALL_LEFT => (0..tiles.y)
+                .map(|y| IVec2::new(0, segment * tiles.y + y))
+                .collect(),
+            _ => panic!("not a single wall: {wall:#x}"),
+        };
+
+        segment_tiles.into_iter().any(|tile| !self.is_wall(tile))
+    }
+
+    fn is_wall(&self, tile: IVec2) -> bool {
+        self.walls[(tile.y * self.size.x + tile.x) as usize]
+    }
+
+    /// Read the walls of a level `cells` grid cells across and down, from the
+    /// first of its layers which has wall cells.
+    pub(crate) fn from_layers(
+        layers: &[LayerInstanceJson],
+        wall_values: &WallValues,
+        cells: IVec2,
+    ) -> Result<Self, WallGridError> {
+        let (layer, wall_value) = layers
+            .iter()
+            .find_map(|layer| {
+                wall_values
+                    .0
+                    .iter()
+                    .find(|(uid, _)| *uid == layer.layer_def_uid)
+                    .map(|(_, wall_value)| (layer, *wall_value))
+            })
+            .ok_or(WallGridError::Missing)?;
+
+        let size = IVec2::new(layer.columns, layer.rows);
+        let wrong_size = || WallGridError::WrongSize {
+            columns: layer.columns,
+            rows: layer.rows,
+            grid_size: layer.grid_size,
+        };
+
+        if size * layer.grid_size != cells * LEVEL_SIZE as i32 || size % cells != IVec2::ZERO {
+            return Err(wrong_size());
+        }
+
+        Self::new(size, &layer.int_grid_csv, wall_value).ok_or_else(wrong_size)
+    }
+}
+
+/// Reasons the walls of a level can't be read.
+#[derive(Debug, thiserror::Error)]
+pub enum WallGridError {
+    #[error("no IntGrid layer with a {WALL_VALUE} value")]
+    Missing,
+    #[error(
+        "an IntGrid of {columns}x{rows} tiles of {grid_size} pixels, which doesn't fit its \
+         cells of {} pixels",
+        LEVEL_SIZE
+    )]
+    WrongSize {
+        columns: i32,
+        rows: i32,
+        grid_size: i32,
+    },
+}
+
+/// The uid of every IntGrid layer definition with a [WALL_VALUE], along with
+/// the value of its wall cells.
+pub(crate) struct WallValues(Vec<(i64, i64)>);
+
+impl WallValues {
+    pub(crate) fn new(layers: &[LayerDefJson]) -> Self {
+        Self(
+            layers
+                .iter()
+                .flat_map(|layer| {
+                    layer
+                        .int_grid_values
+                        .iter()
+                        .filter(|value| value.identifier.as_deref() == Some(WALL_VALUE))
+                        .map(|value| (layer.uid, value.value))
+                })
+                .collect(),
+        )
+    }
+}
+
+// Just enough of the LDtk project JSON format to read the IntGrid layers.
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct LayerDefJson {
+    uid: i64,
+    #[serde(default)]
+    int_grid_values: Vec<IntGridValueJson>,
+}
+
+#[derive(Deserialize)]
+struct IntGridValueJson {
+    value: i64,
+    identifier: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct LayerInstanceJson {
+    #[serde(rename = "__cWid")]
+    columns: i32,
+    #[serde(rename = "__cHei")]
+    rows: i32,
+    #[serde(rename = "__gridSize")]
+    grid_size: i32,
+    layer_def_uid: i64,
+    #[serde(default)]
+    int_grid_csv: Vec<i64>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gaps_are_found_per_segment() {
+        // A room two cells across, of 3x3 tiles each, with a gap in the right
+        // half of its top edge and in its left edge.
+        #[rustfmt::skip]
+        let values = [
+            1, 1, 1, 1, 0, 1,
+            0, 0, 0, 0, 0, 1,
+            1, 1, 1, 1, 1, 1,
+        ];
+        let walls = WallGrid::new(IVec2::new(6, 3), &values, 1).unwrap();
+        let cells = IVec2::new(2, 1);
+
+        assert!(!walls.has_gap(cells, WALL_UP, 0));
+        assert!(walls.has_gap(cells, WALL_UP, 1));
+        assert!(!walls.has_gap(cells, WALL_RIGHT, 0));
+        assert!(!walls.has_gap(cells, WALL_DOWN, 0));
+        assert!(!walls.has_gap(cells, WALL_DOWN, 1));
+        assert!(walls.has_gap(cells, WALL_LEFT, 0));
+
+        assert!(WallGrid::new(IVec2::new(6, 2), &values, 1).is_none());
+    }
+}